@@ -0,0 +1,340 @@
+use crate::entity::pathfinder::{D4, D8, corner_free};
+use crate::entity::clearance::Clearance;
+use crate::entity::grid::Grid;
+use crate::entity::{FastHash, gen_hash};
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Reverse;
+
+/// STRAIGHT is multiplier of tile cost for orthogonal move
+pub const STRAIGHT: i32 = 10;
+/// DIAGONAL is multiplier of tile cost for diagonal move, it approximates √2 * STRAIGHT
+pub const DIAGONAL: i32 = 14;
+
+type Nodes = HashMap<(usize, usize), (i32, (usize, usize)), FastHash>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Heuristic {
+    /// Manhattan is exact for 4 directional movement
+    Manhattan,
+    /// Octile is exact for 8 directional movement
+    Octile,
+    /// Euclidean is straight line distance, it is exact for any angle movement
+    Euclidean,
+}
+
+impl Heuristic {
+    #[inline]
+    pub fn estimate(&self, a: (usize, usize), b: (usize, usize)) -> i32 {
+        let dx = (a.0 as i32 - b.0 as i32).abs();
+        let dy = (a.1 as i32 - b.1 as i32).abs();
+        match self {
+            Heuristic::Manhattan => (dx + dy) * STRAIGHT,
+            Heuristic::Octile => dx.max(dy) * STRAIGHT + dx.min(dy) * (DIAGONAL - STRAIGHT),
+            Heuristic::Euclidean => (((dx * dx + dy * dy) as f32).sqrt() * STRAIGHT as f32) as i32,
+        }
+    }
+}
+
+/// Search is point to point A* over the same cost grid PathFinder uses, `-1` is impassable tile
+/// and any other value is cost of entering the tile. Diagonal move is allowed only if both tiles
+/// it passes by are passable, same as in fields of `PathFinder`.
+#[derive(Copy, Clone, Debug)]
+pub struct Search {
+    pub diagonal: bool,
+    pub heuristic: Heuristic,
+    /// any_angle turns search to Theta*, node can be reached from parent of its predecessor
+    /// in straight line if there is line of sight between them so paths are not bound to
+    /// eight directions. Path then contains only the turning points.
+    pub any_angle: bool,
+}
+
+impl Search {
+    pub const DEFAULT: Self = Search { diagonal: true, heuristic: Heuristic::Octile, any_angle: false };
+    pub const THETA: Self = Search { diagonal: true, heuristic: Heuristic::Euclidean, any_angle: true };
+
+    /// new creates search with heuristic matching the movement, Manhattan heuristic with
+    /// diagonal moves finds paths faster but they does not have to be the shortest
+    #[inline]
+    pub fn new(diagonal: bool) -> Self {
+        Self { diagonal, heuristic: if diagonal {Heuristic::Octile} else {Heuristic::Manhattan}, any_angle: false }
+    }
+
+    /// find returns path from start to goal including both, empty if goal is unreachable
+    pub fn find(&self, start: (usize, usize), goal: (usize, usize), costs: &Grid<i32>) -> Vec<(usize, usize)> {
+        let (w, h) = costs.size();
+        match self.find_in(start, goal, costs, (0, 0, w, h), min_cost(costs)) {
+            Some((_, path)) => path,
+            None => vec![],
+        }
+    }
+
+    /// find_sized returns path for unit that needs given clearance, see `Clearance::required`
+    pub fn find_sized(&self, start: (usize, usize), goal: (usize, usize), costs: &Grid<i32>, clearance: &Clearance, required: i32) -> Vec<(usize, usize)> {
+        let (w, h) = costs.size();
+        match self.find_where(start, goal, costs, (0, 0, w, h), min_cost(costs), |pos| clearance.fits(pos, required)) {
+            Some((_, path)) => path,
+            None => vec![],
+        }
+    }
+
+    /// find_in searches only inside bounds given as (min x, min y, max x, max y) with max
+    /// exclusive, returns cost of path together with the path
+    #[inline]
+    pub(crate) fn find_in(&self, start: (usize, usize), goal: (usize, usize), costs: &Grid<i32>, bounds: Bounds, min_cost: i32) -> Option<(i32, Vec<(usize, usize)>)> {
+        self.find_where(start, goal, costs, bounds, min_cost, |_| true)
+    }
+
+    /// find_where is find_in that also skips passable tiles for which allowed returns false
+    fn find_where<P: Fn((usize, usize)) -> bool>(&self, start: (usize, usize), goal: (usize, usize), costs: &Grid<i32>, bounds: Bounds, min_cost: i32, allowed: P) -> Option<(i32, Vec<(usize, usize)>)> {
+        if !inside(start, bounds) || !inside(goal, bounds) ||
+            costs[start] == -1 || costs[goal] == -1 || !allowed(start) || !allowed(goal) {
+            return None;
+        }
+
+        let mut open = BinaryHeap::new();
+        let mut nodes: Nodes = HashMap::with_hasher(gen_hash());
+        nodes.insert(start, (0, start));
+        open.push(Reverse((self.heuristic.estimate(start, goal) * min_cost, 0, start)));
+
+        while let Some(Reverse((_, g, current))) = open.pop() {
+            if current == goal {
+                return Some((g, reconstruct(&nodes, start, goal)));
+            }
+            let (best, parent) = nodes[&current];
+            if g > best {
+                continue;
+            }
+            let parent_g = nodes[&parent].0;
+
+            neighbours_where(costs, current, self.diagonal, bounds, &allowed, |pos, cost| {
+                let line = if self.any_angle {line_cost_where(costs, parent, pos, &allowed)} else {None};
+                let (supposed, via) = match line {
+                    Some(c) => (parent_g + c, parent),
+                    None => (g + cost, current),
+                };
+                if nodes.get(&pos).is_some_and(|n| n.0 <= supposed) {
+                    return;
+                }
+
+                nodes.insert(pos, (supposed, via));
+                open.push(Reverse((supposed + self.heuristic.estimate(pos, goal) * min_cost, supposed, pos)));
+            });
+        }
+
+        None
+    }
+}
+
+/// Bounds is rectangular area of grid as (min x, min y, max x, max y), max is exclusive
+pub(crate) type Bounds = (usize, usize, usize, usize);
+
+#[inline]
+pub(crate) fn inside(pos: (usize, usize), bounds: Bounds) -> bool {
+    pos.0 >= bounds.0 && pos.1 >= bounds.1 && pos.0 < bounds.2 && pos.1 < bounds.3
+}
+
+/// min_cost returns lowest cost of passable tile, it keeps heuristics admissible
+pub(crate) fn min_cost(costs: &Grid<i32>) -> i32 {
+    costs.iter().filter(|c| **c != -1).min().cloned().unwrap_or(0)
+}
+
+/// neighbours calls f with every tile reachable from current in one move and cost of the move
+#[inline]
+pub(crate) fn neighbours<F: FnMut((usize, usize), i32)>(costs: &Grid<i32>, current: (usize, usize), diagonal: bool, bounds: Bounds, f: F) {
+    neighbours_where(costs, current, diagonal, bounds, |_| true, f)
+}
+
+/// neighbours_where is neighbours that treats tiles for which allowed returns false as impassable
+#[inline]
+pub(crate) fn neighbours_where<P: Fn((usize, usize)) -> bool, F: FnMut((usize, usize), i32)>(costs: &Grid<i32>, current: (usize, usize), diagonal: bool, bounds: Bounds, allowed: P, mut f: F) {
+    let directions: &[(i32, i32)] = if diagonal {&D8} else {&D4};
+    for d in directions.iter() {
+        let pos = (current.0 as i32 + d.0, current.1 as i32 + d.1);
+        if pos.0 < bounds.0 as i32 || pos.1 < bounds.1 as i32 || pos.0 >= bounds.2 as i32 || pos.1 >= bounds.3 as i32 {
+            continue;
+        }
+        let pos = (pos.0 as usize, pos.1 as usize);
+
+        let cost = costs[pos];
+        if cost == -1 || !allowed(pos) {
+            continue;
+        }
+
+        if !corner_free(current, pos, |x, y| costs[(x, y)] != -1 && allowed((x, y))) {
+            continue;
+        }
+
+        f(pos, cost * if d.0 == 0 || d.1 == 0 {STRAIGHT} else {DIAGONAL});
+    }
+}
+
+/// find_path returns shortest path from start to goal with diagonal moves allowed, see `Search`
+#[inline]
+pub fn find_path(start: (usize, usize), goal: (usize, usize), costs: &Grid<i32>) -> Vec<(usize, usize)> {
+    Search::DEFAULT.find(start, goal, costs)
+}
+
+/// supercover calls f with every tile that line between centers of a and b passes trough in
+/// order from a to b, it stops and returns false as soon as f does. When line passes exactly
+/// trough a corner, both tiles touching it are visited with corner set to true before the
+/// diagonal one.
+pub fn supercover<F: FnMut((usize, usize), bool) -> bool>(a: (usize, usize), b: (usize, usize), mut f: F) -> bool {
+    let (mut x, mut y) = (a.0 as i64, a.1 as i64);
+    let (dx, dy) = ((b.0 as i64 - x).abs(), (b.1 as i64 - y).abs());
+    let (sx, sy) = ((b.0 as i64 - x).signum(), (b.1 as i64 - y).signum());
+    let mut error = dx - dy;
+    let mut steps = dx + dy;
+
+    if !f(a, false) {
+        return false;
+    }
+    while steps > 0 {
+        if error > 0 {
+            x += sx;
+            error -= 2 * dy;
+        } else if error < 0 {
+            y += sy;
+            error += 2 * dx;
+        } else {
+            if !f(((x + sx) as usize, y as usize), true) || !f((x as usize, (y + sy) as usize), true) {
+                return false;
+            }
+            x += sx;
+            y += sy;
+            error += 2 * (dx - dy);
+            steps -= 1;
+        }
+        steps -= 1;
+        if !f((x as usize, y as usize), false) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// line_of_sight returns whether straight line between centers of two tiles crosses only
+/// passable tiles, line passing trough corner needs both tiles touching it passable
+#[inline]
+pub fn line_of_sight(costs: &Grid<i32>, a: (usize, usize), b: (usize, usize)) -> bool {
+    supercover(a, b, |pos, _| costs[pos] != -1)
+}
+
+/// line_cost returns cost of moving from a to b in straight line in units of `Search`, average
+/// cost of crossed tiles is multiplied by length of the line. None means there is no line of
+/// sight.
+#[inline]
+pub fn line_cost(costs: &Grid<i32>, a: (usize, usize), b: (usize, usize)) -> Option<i32> {
+    line_cost_where(costs, a, b, |_| true)
+}
+
+fn line_cost_where<P: Fn((usize, usize)) -> bool>(costs: &Grid<i32>, a: (usize, usize), b: (usize, usize), allowed: P) -> Option<i32> {
+    if a == b {
+        return Some(0);
+    }
+
+    let mut sum = 0i64;
+    let mut count = 0i64;
+    let visible = supercover(a, b, |pos, corner| {
+        let cost = costs[pos];
+        if !corner && pos != a {
+            sum += cost as i64;
+            count += 1;
+        }
+        cost != -1 && allowed(pos)
+    });
+    if !visible {
+        return None;
+    }
+
+    let (dx, dy) = (a.0 as f32 - b.0 as f32, a.1 as f32 - b.1 as f32);
+    let length = (dx * dx + dy * dy).sqrt() * STRAIGHT as f32;
+    Some((sum as f32 / count as f32 * length).round() as i32)
+}
+
+/// smooth pulls the path tight, it keeps only tiles where path has to turn so consecutive
+/// tiles of result have line of sight. Costs of tiles are not considered, only passability.
+pub fn smooth(path: &[(usize, usize)], costs: &Grid<i32>) -> Vec<(usize, usize)> {
+    if path.len() <= 2 {
+        return path.to_vec();
+    }
+
+    let mut result = vec![path[0]];
+    for i in 1..path.len() - 1 {
+        if !line_of_sight(costs, *result.last().unwrap(), path[i + 1]) {
+            result.push(path[i]);
+        }
+    }
+    result.push(path[path.len() - 1]);
+    result
+}
+
+fn reconstruct(nodes: &Nodes, start: (usize, usize), goal: (usize, usize)) -> Vec<(usize, usize)> {
+    let mut path = vec![goal];
+    let mut current = goal;
+    while current != start {
+        current = nodes[&current].1;
+        path.push(current);
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::astar::{find_path, line_of_sight, smooth, Search};
+    use crate::entity::grid::Grid;
+
+    #[test]
+    fn find_test() {
+        let mut costs = Grid::new(10, 10, 1);
+        for y in 0..9 {
+            costs[(5, y)] = -1;
+        }
+
+        let path = find_path((0, 0), (9, 0), &costs);
+        assert_eq!(Some(&(0, 0)), path.first());
+        assert_eq!(Some(&(9, 0)), path.last());
+        assert!(path.contains(&(5, 9)));
+        assert_eq!(21, path.len());
+
+        let path = Search::new(false).find((0, 0), (9, 0), &costs);
+        assert_eq!(28, path.len());
+
+        costs[(5, 9)] = -1;
+        assert!(find_path((0, 0), (9, 0), &costs).is_empty());
+    }
+
+    #[test]
+    fn line_of_sight_test() {
+        let mut costs = Grid::new(10, 10, 1);
+        costs[(2, 2)] = -1;
+        assert!(line_of_sight(&costs, (0, 0), (9, 3)));
+        assert!(!line_of_sight(&costs, (0, 0), (4, 4)));
+        assert!(!line_of_sight(&costs, (1, 2), (3, 2)));
+        assert!(!line_of_sight(&costs, (1, 3), (3, 1)));
+        assert!(line_of_sight(&costs, (1, 3), (3, 5)));
+
+        let path = find_path((0, 1), (9, 8), &costs);
+        let smoothed = smooth(&path, &costs);
+        assert!(smoothed.len() < path.len());
+        assert_eq!((0, 1), smoothed[0]);
+        assert_eq!(Some(&(9, 8)), smoothed.last());
+        for pair in smoothed.windows(2) {
+            assert!(line_of_sight(&costs, pair[0], pair[1]));
+        }
+    }
+
+    #[test]
+    fn theta_test() {
+        let mut costs = Grid::new(20, 20, 1);
+        for y in 0..15 {
+            costs[(10, y)] = -1;
+        }
+
+        assert_eq!(vec![(0, 0), (19, 7)], Search::THETA.find((0, 0), (19, 7), &Grid::new(20, 20, 1)));
+        let path = Search::THETA.find((0, 0), (19, 0), &costs);
+        assert_eq!(4, path.len());
+        assert!(path.contains(&(9, 15)) && path.contains(&(11, 15)));
+    }
+}
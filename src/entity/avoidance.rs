@@ -0,0 +1,432 @@
+use crate::entity::scanner::Scanner;
+use crate::entity::steering::Agent;
+use crate::math::rect::Rect;
+use crate::math::sweep::closest_on_segment;
+use crate::math::vect::Vect;
+use crate::math::clamp;
+use std::thread;
+
+const EPSILON: f32 = 0.00001;
+
+/// Avoidance holds parameters of ORCA (optimal reciprocal collision avoidance). Agents look
+/// time_horizon seconds ahead for collisions with each other and obstacle_horizon seconds
+/// for collisions with obstacles, only max_neighbours closest agents inside neighbour_distance
+/// are considered.
+#[derive(Copy, Clone, Debug)]
+pub struct Avoidance {
+    pub neighbour_distance: f32,
+    pub max_neighbours: usize,
+    pub time_horizon: f32,
+    pub obstacle_horizon: f32,
+}
+
+impl Default for Avoidance {
+    fn default() -> Self {
+        Self { neighbour_distance: 30f32, max_neighbours: 10, time_horizon: 2f32, obstacle_horizon: 1f32 }
+    }
+}
+
+/// Line is half-plane of allowed velocities, allowed side is on the left of direction
+#[derive(Copy, Clone, Debug)]
+struct Line {
+    point: Vect,
+    dir: Vect,
+}
+
+/// Solver computes collision free velocities, it only keeps buffers so one solver per thread
+/// does not allocate every call
+#[derive(Default)]
+pub struct Solver {
+    lines: Vec<Line>,
+    projected: Vec<Line>,
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// velocity returns velocity closest to preferred that does not collide with neighbours
+    /// and obstacle segments within the horizons. Each agent takes half of the responsibility
+    /// for avoiding the other, obstacles do not move so agent avoids them alone. When crowd is
+    /// too dense for collision free velocity the one that penetrates the least is returned.
+    pub fn velocity(
+        &mut self,
+        agent: &Agent,
+        preferred: Vect,
+        neighbours: &[Agent],
+        obstacles: &[(Vect, Vect)],
+        avoidance: &Avoidance,
+        delta: f32,
+    ) -> Vect {
+        self.lines.clear();
+        for &(a, b) in obstacles.iter() {
+            let closest = closest_on_segment(agent.pos, a, b);
+            let u = relative(agent.pos.to(closest), agent.vel, agent.radius, avoidance.obstacle_horizon, delta);
+            self.lines.push(Line { point: agent.vel + u.0, dir: u.1 });
+        }
+        let obstacle_lines = self.lines.len();
+
+        for other in neighbours.iter() {
+            let u = relative(
+                agent.pos.to(other.pos),
+                agent.vel - other.vel,
+                agent.radius + other.radius,
+                avoidance.time_horizon,
+                delta,
+            );
+            self.lines.push(Line { point: agent.vel + u.0 * 0.5, dir: u.1 });
+        }
+
+        let mut result = Vect::ZERO;
+        let failed = program2(&self.lines, agent.max_speed, preferred, false, &mut result);
+        if failed < self.lines.len() {
+            self.program3(obstacle_lines, failed, agent.max_speed, &mut result);
+        }
+        result
+    }
+
+    /// program3 finds velocity that violates constraints the least, obstacle lines are never
+    /// violated
+    fn program3(&mut self, obstacle_lines: usize, begin: usize, radius: f32, result: &mut Vect) {
+        let Self { lines, projected } = self;
+        let mut distance = 0f32;
+        for i in begin..lines.len() {
+            let line = lines[i];
+            if line.dir.cross(line.point - *result) <= distance {
+                continue;
+            }
+
+            projected.clear();
+            projected.extend_from_slice(&lines[..obstacle_lines]);
+            for other in lines[obstacle_lines..i].iter() {
+                let denominator = line.dir.cross(other.dir);
+                let point = if denominator.abs() <= EPSILON {
+                    if line.dir.dot(other.dir) > 0f32 {
+                        continue;
+                    }
+                    (line.point + other.point) * 0.5
+                } else {
+                    line.point + line.dir * (other.dir.cross(line.point - other.point) / denominator)
+                };
+                projected.push(Line { point, dir: (other.dir - line.dir).norm() });
+            }
+
+            let previous = *result;
+            if program2(projected, radius, Vect::new(-line.dir.y, line.dir.x), true, result) < projected.len() {
+                *result = previous;
+            }
+            distance = line.dir.cross(line.point - *result);
+        }
+    }
+}
+
+/// relative returns smallest change of relative velocity that avoids collision within horizon
+/// and direction of the border of allowed half-plane
+fn relative(pos: Vect, vel: Vect, radius: f32, horizon: f32, delta: f32) -> (Vect, Vect) {
+    let dist_sq = pos.dot(pos);
+    let radius_sq = radius * radius;
+
+    if dist_sq <= radius_sq {
+        // already colliding, get apart within one step
+        let w = vel - pos / delta;
+        let len = w.len();
+        let unit = if len > 0f32 { w / len } else { Vect::RIGHT };
+        return (unit * (radius / delta - len), Vect::new(unit.y, -unit.x));
+    }
+
+    let w = vel - pos / horizon;
+    let w_len_sq = w.dot(w);
+    let dot = w.dot(pos);
+    if dot < 0f32 && dot * dot > radius_sq * w_len_sq {
+        // projecting on the cut-off circle
+        let len = w_len_sq.sqrt();
+        let unit = w / len;
+        return (unit * (radius / horizon - len), Vect::new(unit.y, -unit.x));
+    }
+
+    // projecting on the legs
+    let leg = (dist_sq - radius_sq).sqrt();
+    let dir = if pos.cross(w) > 0f32 {
+        Vect::new(pos.x * leg - pos.y * radius, pos.x * radius + pos.y * leg) / dist_sq
+    } else {
+        Vect::new(pos.x * leg + pos.y * radius, -pos.x * radius + pos.y * leg) / -dist_sq
+    };
+    (dir * vel.dot(dir) - vel, dir)
+}
+
+/// program1 solves the program on line of given index restricted by all lines before it and
+/// by circle of radius, returns false if it is infeasible
+fn program1(lines: &[Line], index: usize, radius: f32, optimal: Vect, direction: bool, result: &mut Vect) -> bool {
+    let line = lines[index];
+    let dot = line.point.dot(line.dir);
+    let discriminant = dot * dot + radius * radius - line.point.dot(line.point);
+    if discriminant < 0f32 {
+        return false;
+    }
+
+    let root = discriminant.sqrt();
+    let mut left = -dot - root;
+    let mut right = -dot + root;
+    for other in lines[..index].iter() {
+        let denominator = line.dir.cross(other.dir);
+        let numerator = other.dir.cross(line.point - other.point);
+        if denominator.abs() <= EPSILON {
+            if numerator < 0f32 {
+                return false;
+            }
+            continue;
+        }
+
+        let t = numerator / denominator;
+        if denominator >= 0f32 {
+            right = right.min(t);
+        } else {
+            left = left.max(t);
+        }
+        if left > right {
+            return false;
+        }
+    }
+
+    let t = if direction {
+        if optimal.dot(line.dir) > 0f32 { right } else { left }
+    } else {
+        line.dir.dot(optimal - line.point).max(left).min(right)
+    };
+    *result = line.point + line.dir * t;
+    true
+}
+
+/// program2 finds velocity closest to optimal inside circle of radius that satisfies all
+/// lines, if direction is true optimal is unit direction to go as far as possible in. It
+/// returns index of the first line it failed on or count of lines on success.
+fn program2(lines: &[Line], radius: f32, optimal: Vect, direction: bool, result: &mut Vect) -> usize {
+    *result = if direction {
+        optimal * radius
+    } else if optimal.dot(optimal) > radius * radius {
+        optimal.norm() * radius
+    } else {
+        optimal
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.dir.cross(line.point - *result) > 0f32 {
+            let previous = *result;
+            if !program1(lines, i, radius, optimal, direction, result) {
+                *result = previous;
+                return i;
+            }
+        }
+    }
+    lines.len()
+}
+
+/// Crowd moves agents towards their preferred velocities without collisions. Agents are found
+/// trough Scanner, obstacle segments are stored in second Scanner of the same size in every
+/// cell they pass trough. Preferred velocity usually comes from flow field, for example
+/// direction of `PathFinder::get_step` times speed. Agent is identified by its index, removing
+/// agent moves the last one to its index.
+pub struct Crowd {
+    agents: Vec<Agent>,
+    preferred: Vec<Vect>,
+    velocities: Vec<Vect>,
+    segments: Vec<(Vect, Vect)>,
+    scanner: Scanner<usize>,
+    obstacles: Scanner<usize>,
+    pub avoidance: Avoidance,
+    workers: usize,
+}
+
+impl Crowd {
+    /// new creates empty crowd, tile size should be about neighbour distance
+    pub fn new(w: usize, h: usize, tile_size: Vect, avoidance: Avoidance, workers: usize) -> Self {
+        Self {
+            agents: vec![],
+            preferred: vec![],
+            velocities: vec![],
+            segments: vec![],
+            scanner: Scanner::new(w, h, tile_size),
+            obstacles: Scanner::new(w, h, tile_size),
+            avoidance,
+            workers: workers.max(1),
+        }
+    }
+
+    /// add adds agent and returns its index, its preferred velocity is zero
+    pub fn add(&mut self, agent: Agent) -> usize {
+        let index = self.agents.len();
+        self.scanner.insert(agent.pos, index);
+        self.agents.push(agent);
+        self.preferred.push(Vect::ZERO);
+        index
+    }
+
+    /// remove removes agent, the last agent takes its index
+    pub fn remove(&mut self, index: usize) -> Agent {
+        let last = self.agents.len() - 1;
+        self.scanner.remove(self.agents[index].pos, index);
+        if index != last {
+            self.scanner.remove(self.agents[last].pos, last);
+            self.scanner.insert(self.agents[last].pos, index);
+        }
+        self.preferred.swap_remove(index);
+        self.agents.swap_remove(index)
+    }
+
+    /// add_obstacle adds static segment agents cannot pass trough
+    pub fn add_obstacle(&mut self, a: Vect, b: Vect) {
+        let id = self.segments.len();
+        self.segments.push((a, b));
+        let mut cells = vec![];
+        self.obstacles.walk_cells(a, b, |x, y| cells.push((x, y)));
+        for cell in cells {
+            self.obstacles.map[cell].insert(id);
+        }
+    }
+
+    /// set_preferred sets velocity agent would move with if it was alone
+    #[inline]
+    pub fn set_preferred(&mut self, index: usize, vel: Vect) {
+        self.preferred[index] = vel;
+    }
+
+    #[inline]
+    pub fn preferred(&self, index: usize) -> Vect {
+        self.preferred[index]
+    }
+
+    #[inline]
+    pub fn agents(&self) -> &[Agent] {
+        &self.agents
+    }
+
+    #[inline]
+    pub fn scanner(&self) -> &Scanner<usize> {
+        &self.scanner
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+
+    /// update computes new velocities of all agents in parallel on given count of threads and
+    /// moves agents by delta
+    pub fn update(&mut self, delta: f32) {
+        let Self { agents, preferred, velocities, segments, scanner, obstacles, avoidance, workers } = self;
+        velocities.resize(agents.len(), Vect::ZERO);
+        if agents.is_empty() {
+            return;
+        }
+
+        let chunk = agents.len().div_ceil(clamp(*workers, 1, agents.len()));
+        let (view, preferred, segments, cells, obstacles, avoidance) =
+            (&*agents, &*preferred, &*segments, &*scanner, &*obstacles, &*avoidance);
+        thread::scope(|s| {
+            for (i, part) in velocities.chunks_mut(chunk).enumerate() {
+                s.spawn(move || {
+                    let mut solver = Solver::new();
+                    let mut collector = vec![];
+                    let mut neighbours = vec![];
+                    let mut nearby = vec![];
+                    for (j, vel) in part.iter_mut().enumerate() {
+                        let index = i * chunk + j;
+                        let agent = &view[index];
+
+                        collector.clear();
+                        cells.query(&Rect::cube(agent.pos, avoidance.neighbour_distance), &mut collector);
+                        neighbours.clear();
+                        neighbours.extend(collector.iter()
+                            .filter(|&&o| o != index)
+                            .map(|&o| view[o])
+                            .filter(|o| o.pos.dist(agent.pos) < avoidance.neighbour_distance));
+                        if neighbours.len() > avoidance.max_neighbours {
+                            neighbours.sort_by(|a, b| a.pos.dist(agent.pos).partial_cmp(&b.pos.dist(agent.pos)).unwrap());
+                            neighbours.truncate(avoidance.max_neighbours);
+                        }
+
+                        let range = avoidance.obstacle_horizon * agent.max_speed + agent.radius;
+                        collector.clear();
+                        obstacles.query(&Rect::cube(agent.pos, range), &mut collector);
+                        collector.sort_unstable();
+                        collector.dedup();
+                        nearby.clear();
+                        nearby.extend(collector.iter()
+                            .map(|&o| segments[o])
+                            .filter(|&(a, b)| closest_on_segment(agent.pos, a, b).dist(agent.pos) < range));
+
+                        *vel = solver.velocity(agent, preferred[index], &neighbours, &nearby, avoidance, delta);
+                    }
+                });
+            }
+        });
+
+        for (index, (agent, &vel)) in agents.iter_mut().zip(velocities.iter()).enumerate() {
+            let old = agent.pos;
+            agent.vel = vel;
+            agent.pos += vel * delta;
+            scanner.update(old, agent.pos, index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::avoidance::{Avoidance, Crowd};
+    use crate::entity::steering::Agent;
+    use crate::math::vect::Vect;
+
+    #[test]
+    fn crowd_test() {
+        let mut crowd = Crowd::new(10, 10, Vect::mirror(20f32), Avoidance::default(), 4);
+        // two groups swapping sides
+        for i in 0..10 {
+            let left = crowd.add(Agent::new(Vect::new(40f32, 60f32 + i as f32 * 8f32), 2f32, 10f32, 0f32));
+            crowd.set_preferred(left, Vect::new(10f32, 0f32));
+            let right = crowd.add(Agent::new(Vect::new(160f32, 62f32 + i as f32 * 8f32), 2f32, 10f32, 0f32));
+            crowd.set_preferred(right, Vect::new(-10f32, 0f32));
+        }
+
+        for _ in 0..200 {
+            crowd.update(0.1);
+            let agents = crowd.agents();
+            for (i, a) in agents.iter().enumerate() {
+                for b in agents[i + 1..].iter() {
+                    assert!(a.pos.dist(b.pos) > a.radius + b.radius - 0.1);
+                }
+            }
+        }
+
+        for (i, agent) in crowd.agents().iter().enumerate() {
+            assert!(if i % 2 == 0 { agent.pos.x > 150f32 } else { agent.pos.x < 50f32 });
+        }
+    }
+
+    #[test]
+    fn obstacle_test() {
+        let mut crowd = Crowd::new(10, 10, Vect::mirror(20f32), Avoidance::default(), 1);
+        // wall with gap between 95 and 105
+        crowd.add_obstacle(Vect::new(100f32, 0f32), Vect::new(100f32, 95f32));
+        crowd.add_obstacle(Vect::new(100f32, 105f32), Vect::new(100f32, 200f32));
+        for &y in [50f32, 100f32, 150f32].iter() {
+            let agent = crowd.add(Agent::new(Vect::new(60f32, y), 2f32, 10f32, 0f32));
+            crowd.set_preferred(agent, Vect::new(10f32, 0f32));
+        }
+
+        for _ in 0..100 {
+            crowd.update(0.1);
+            for agent in crowd.agents().iter() {
+                assert!(agent.pos.x < 100f32 - agent.radius + 0.1 || (agent.pos.y - 100f32).abs() < 5f32);
+            }
+        }
+
+        let passed: Vec<_> = crowd.agents().iter().map(|a| a.pos.x > 100f32).collect();
+        assert_eq!(vec![false, true, false], passed);
+    }
+}
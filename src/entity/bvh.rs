@@ -0,0 +1,367 @@
+use crate::math::rect::Rect;
+use crate::math::vect::Vect;
+use crate::entity::spatial::SpatialIndex;
+use crate::entity::{FastHash, gen_hash};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+const NULL: usize = usize::MAX;
+
+struct Node<T> {
+    bounds: Rect,
+    fat: Rect,
+    parent: usize,
+    left: usize,
+    right: usize,
+    height: i32,
+    id: Option<T>,
+}
+
+impl<T> Node<T> {
+    #[inline]
+    fn is_leaf(&self) -> bool {
+        self.left == NULL
+    }
+}
+
+/// Bvh is dynamic AABB tree. Leafs store bounds fattened by margin so small movements does not
+/// change the tree at all, tree is kept balanced with rotations on every insertion and removal.
+pub struct Bvh<T: Hash + Eq + Copy> {
+    nodes: Vec<Node<T>>,
+    free: Vec<usize>,
+    leafs: HashMap<T, usize, FastHash>,
+    root: usize,
+    margin: f32,
+}
+
+impl<T: Hash + Eq + Copy> Bvh<T> {
+    /// new creates empty tree, margin is how much are leaf bounds fattened
+    pub fn new(margin: f32) -> Self {
+        Self {
+            nodes: vec![],
+            free: vec![],
+            leafs: HashMap::with_hasher(gen_hash()),
+            root: NULL,
+            margin,
+        }
+    }
+
+    /// len returns count of stored items
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.leafs.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.leafs.is_empty()
+    }
+
+    /// height returns height of the tree, 0 if tree has at most one item
+    #[inline]
+    pub fn height(&self) -> i32 {
+        if self.root == NULL {0} else {self.nodes[self.root].height}
+    }
+
+    fn alloc(&mut self, bounds: Rect, fat: Rect, id: Option<T>) -> usize {
+        let node = Node { bounds, fat, parent: NULL, left: NULL, right: NULL, height: 0, id };
+        match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    #[inline]
+    fn refit(&mut self, i: usize) {
+        let (l, r) = (self.nodes[i].left, self.nodes[i].right);
+        self.nodes[i].height = 1 + self.nodes[l].height.max(self.nodes[r].height);
+        self.nodes[i].fat = self.nodes[l].fat.union(&self.nodes[r].fat);
+    }
+
+    fn fix_upwards(&mut self, mut i: usize) {
+        while i != NULL {
+            i = self.balance(i);
+            self.refit(i);
+            i = self.nodes[i].parent;
+        }
+    }
+
+    #[inline]
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if parent == NULL {
+            self.root = new;
+        } else if self.nodes[parent].left == old {
+            self.nodes[parent].left = new;
+        } else {
+            self.nodes[parent].right = new;
+        }
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        if self.root == NULL {
+            self.root = leaf;
+            self.nodes[leaf].parent = NULL;
+            return;
+        }
+
+        let fat = self.nodes[leaf].fat;
+        let mut i = self.root;
+        while !self.nodes[i].is_leaf() {
+            let node = &self.nodes[i];
+            let combined = node.fat.union(&fat).perimeter();
+            let cost = 2f32 * combined;
+            let inheritance = 2f32 * (combined - node.fat.perimeter());
+
+            let child_cost = |c: &Node<T>| {
+                let perimeter = c.fat.union(&fat).perimeter();
+                if c.is_leaf() {
+                    perimeter + inheritance
+                } else {
+                    perimeter - c.fat.perimeter() + inheritance
+                }
+            };
+            let left = child_cost(&self.nodes[node.left]);
+            let right = child_cost(&self.nodes[node.right]);
+
+            if cost < left && cost < right {
+                break;
+            }
+
+            i = if left < right {node.left} else {node.right};
+        }
+
+        let sibling = i;
+        let old_parent = self.nodes[sibling].parent;
+        let union = self.nodes[sibling].fat.union(&fat);
+        let parent = self.alloc(union, union, None);
+        self.nodes[parent].parent = old_parent;
+        self.nodes[parent].height = self.nodes[sibling].height + 1;
+        self.nodes[parent].left = sibling;
+        self.nodes[parent].right = leaf;
+        self.replace_child(old_parent, sibling, parent);
+        self.nodes[sibling].parent = parent;
+        self.nodes[leaf].parent = parent;
+
+        self.fix_upwards(parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if leaf == self.root {
+            self.root = NULL;
+            return;
+        }
+
+        let parent = self.nodes[leaf].parent;
+        let grand = self.nodes[parent].parent;
+        let sibling = if self.nodes[parent].left == leaf {
+            self.nodes[parent].right
+        } else {
+            self.nodes[parent].left
+        };
+
+        self.replace_child(grand, parent, sibling);
+        self.nodes[sibling].parent = grand;
+        self.free.push(parent);
+
+        self.fix_upwards(grand);
+    }
+
+    /// balance rotates the subtree if one branch is higher by more then one, returns new root
+    /// of the subtree
+    fn balance(&mut self, a: usize) -> usize {
+        if self.nodes[a].is_leaf() || self.nodes[a].height < 2 {
+            return a;
+        }
+
+        let b = self.nodes[a].left;
+        let c = self.nodes[a].right;
+        let balance = self.nodes[c].height - self.nodes[b].height;
+
+        if balance > 1 {
+            self.rotate(a, c, b, false)
+        } else if balance < -1 {
+            self.rotate(a, b, c, true)
+        } else {
+            a
+        }
+    }
+
+    /// rotate lifts up child of a, other is the remaining child of a and left says whether
+    /// up is left child of a
+    fn rotate(&mut self, a: usize, up: usize, other: usize, left: bool) -> usize {
+        let f = self.nodes[up].left;
+        let g = self.nodes[up].right;
+
+        self.nodes[up].left = a;
+        self.nodes[up].parent = self.nodes[a].parent;
+        self.nodes[a].parent = up;
+        let parent = self.nodes[up].parent;
+        self.replace_child(parent, a, up);
+
+        let (high, low) = if self.nodes[f].height > self.nodes[g].height {(f, g)} else {(g, f)};
+        self.nodes[up].right = high;
+        if left {
+            self.nodes[a].left = low;
+        } else {
+            self.nodes[a].right = low;
+        }
+        self.nodes[low].parent = a;
+
+        self.nodes[a].fat = self.nodes[other].fat.union(&self.nodes[low].fat);
+        self.nodes[a].height = 1 + self.nodes[other].height.max(self.nodes[low].height);
+        self.nodes[up].fat = self.nodes[a].fat.union(&self.nodes[high].fat);
+        self.nodes[up].height = 1 + self.nodes[a].height.max(self.nodes[high].height);
+
+        up
+    }
+
+    #[inline]
+    fn collect_rect(&self, rect: &Rect, collector: &mut Vec<T>) {
+        if self.root == NULL {
+            return;
+        }
+
+        let mut stack = vec![self.root];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if !node.fat.intersects(rect) {
+                continue;
+            }
+            match node.id {
+                Some(id) => if node.bounds.intersects(rect) {
+                    collector.push(id);
+                },
+                None => {
+                    stack.push(node.left);
+                    stack.push(node.right);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Hash + Eq + Copy> SpatialIndex<T> for Bvh<T> {
+    fn insert(&mut self, bounds: Rect, id: T) {
+        if let Some(leaf) = self.leafs.get(&id) {
+            let leaf = *leaf;
+            self.remove_leaf(leaf);
+            self.free.push(leaf);
+        }
+
+        let leaf = self.alloc(bounds, bounds.expanded(self.margin), Some(id));
+        self.leafs.insert(id, leaf);
+        self.insert_leaf(leaf);
+    }
+
+    fn remove(&mut self, _bounds: Rect, id: T) -> bool {
+        match self.leafs.remove(&id) {
+            Some(leaf) => {
+                self.remove_leaf(leaf);
+                self.free.push(leaf);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn update(&mut self, _old: Rect, new: Rect, id: T) -> bool {
+        let leaf = match self.leafs.get(&id) {
+            Some(leaf) => *leaf,
+            None => return false,
+        };
+
+        self.nodes[leaf].bounds = new;
+        if new.fits_in(&self.nodes[leaf].fat) {
+            return true;
+        }
+
+        self.remove_leaf(leaf);
+        self.nodes[leaf].fat = new.expanded(self.margin);
+        self.insert_leaf(leaf);
+        true
+    }
+
+    #[inline]
+    fn query_rect(&self, rect: &Rect, collector: &mut Vec<T>) {
+        self.collect_rect(rect, collector);
+    }
+
+    #[inline]
+    fn query_point(&self, pos: Vect, collector: &mut Vec<T>) {
+        self.collect_rect(&Rect { min: pos, max: pos }, collector);
+    }
+
+    fn raycast(&self, from: Vect, to: Vect, collector: &mut Vec<T>) {
+        if self.root == NULL {
+            return;
+        }
+
+        let mut hits = vec![];
+        let mut stack = vec![self.root];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if node.fat.ray_cast(from, to).is_none() {
+                continue;
+            }
+            match node.id {
+                Some(id) => if let Some(t) = node.bounds.ray_cast(from, to) {
+                    hits.push((t, id));
+                },
+                None => {
+                    stack.push(node.left);
+                    stack.push(node.right);
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        collector.extend(hits.iter().map(|h| h.1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::bvh::Bvh;
+    use crate::entity::spatial::SpatialIndex;
+    use crate::math::rect::Rect;
+    use crate::math::vect::Vect;
+    use rand::Rng;
+
+    #[test]
+    fn query_test() {
+        let mut tree = Bvh::new(2f32);
+        let mut rng = rand::thread_rng();
+        let mut rects = vec![];
+        for i in 0..500 {
+            let r = Rect::cube(Vect::new(rng.gen::<f32>() * 1000f32, rng.gen::<f32>() * 1000f32), rng.gen::<f32>() * 50f32);
+            tree.insert(r, i);
+            rects.push(r);
+        }
+
+        for (i, rect) in rects.iter_mut().enumerate() {
+            let moved = rect.moved(Vect::new(rng.gen::<f32>() * 10f32, rng.gen::<f32>() * 10f32));
+            assert!(tree.update(*rect, moved, i));
+            *rect = moved;
+        }
+
+        for (i, rect) in rects[..250].iter().enumerate() {
+            assert!(tree.remove(*rect, i));
+        }
+        assert_eq!(250, tree.len());
+        assert!(tree.height() < 20);
+
+        let query = Rect::new(200f32, 300f32, 600f32, 500f32);
+        let mut collector = vec![];
+        tree.query_rect(&query, &mut collector);
+        collector.sort();
+
+        let expected: Vec<usize> = (250..500).filter(|i| rects[*i].intersects(&query)).collect();
+        assert_eq!(expected, collector);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::entity::astar::Bounds;
+use crate::entity::grid::{Grid, D8};
+use std::collections::VecDeque;
+
+/// Clearance is map of distances to the nearest obstacle over cost grid in PathFinder format.
+/// Distance is Chebyshev, impassable tile has 0, tile next to obstacle or edge of the grid has 1
+/// and so on up to the cap. Tile with clearance c can hold square unit up to 2c - 1 tiles
+/// wide centered on it.
+pub struct Clearance {
+    map: Grid<i32>,
+    cap: i32,
+}
+
+impl Clearance {
+    /// new computes clearance of whole grid, values bigger then cap are stored as cap so cap
+    /// should be clearance of the biggest unit, higher cap makes updates more expensive
+    pub fn new(costs: &Grid<i32>, cap: i32) -> Self {
+        let (w, h) = costs.size();
+        let mut clearance = Self { map: Grid::new(w, h, 0), cap: cap.max(1) };
+        clearance.fill(costs, (0, 0, w, h));
+        clearance
+    }
+
+    /// required returns clearance needed by unit covering size x size tiles
+    #[inline]
+    pub fn required(size: usize) -> i32 {
+        size as i32 / 2 + 1
+    }
+
+    #[inline]
+    pub fn get(&self, pos: (usize, usize)) -> i32 {
+        self.map[pos]
+    }
+
+    /// fits returns whether unit that needs given clearance can stand on the tile
+    #[inline]
+    pub fn fits(&self, pos: (usize, usize), required: i32) -> bool {
+        self.map[pos] >= required
+    }
+
+    #[inline]
+    pub fn map(&self) -> &Grid<i32> {
+        &self.map
+    }
+
+    #[inline]
+    pub fn cap(&self) -> i32 {
+        self.cap
+    }
+
+    /// update recomputes clearance around changed tiles, costs has to contain the changes
+    /// already. Returns tiles whose clearance changed together with their old clearance.
+    pub fn update(&mut self, costs: &Grid<i32>, changes: &[(usize, usize, i32)]) -> Vec<(usize, usize, i32)> {
+        let reach = self.cap as usize - 1;
+        let (w, h) = self.map.size();
+        let mut changed = vec![];
+        for change in changes.iter() {
+            let bounds = (
+                change.0.saturating_sub(reach),
+                change.1.saturating_sub(reach),
+                (change.0 + reach + 1).min(w),
+                (change.1 + reach + 1).min(h),
+            );
+
+            let old: Vec<((usize, usize), i32)> = self.map.view(bounds).iter().map(|(pos, v)| (pos, *v)).collect();
+            self.fill(costs, bounds);
+
+            for (pos, value) in old.into_iter() {
+                if self.map[pos] != value {
+                    changed.push((pos.0, pos.1, value));
+                }
+            }
+        }
+        changed
+    }
+
+    /// fill computes clearance of tiles inside bounds, it searches from obstacles no further
+    /// then cap around bounds as farther ones cannot affect the result
+    fn fill(&mut self, costs: &Grid<i32>, bounds: Bounds) {
+        let reach = self.cap as usize;
+        let (w, h) = self.map.size();
+        let window = (
+            bounds.0.saturating_sub(reach),
+            bounds.1.saturating_sub(reach),
+            (bounds.2 + reach).min(w),
+            (bounds.3 + reach).min(h),
+        );
+        let width = window.2 - window.0;
+        let index = |x: usize, y: usize| (y - window.1) * width + x - window.0;
+
+        let mut distances = vec![self.cap; width * (window.3 - window.1)];
+        let mut queue = VecDeque::new();
+        for edge in [false, true].iter() {
+            for y in window.1..window.3 {
+                for x in window.0..window.2 {
+                    let source = if *edge {
+                        x == 0 || y == 0 || x == w - 1 || y == h - 1
+                    } else {
+                        costs[(x, y)] == -1
+                    };
+                    let distance = if *edge {1} else {0};
+                    if source && distances[index(x, y)] > distance {
+                        distances[index(x, y)] = distance;
+                        queue.push_back((x, y));
+                    }
+                }
+            }
+        }
+
+        while let Some((x, y)) = queue.pop_front() {
+            let distance = distances[index(x, y)] + 1;
+            if distance >= self.cap {
+                continue;
+            }
+            for d in D8.iter() {
+                let (nx, ny) = (x as i32 + d.0, y as i32 + d.1);
+                if nx < window.0 as i32 || ny < window.1 as i32 || nx >= window.2 as i32 || ny >= window.3 as i32 {
+                    continue;
+                }
+                let i = index(nx as usize, ny as usize);
+                if distances[i] > distance {
+                    distances[i] = distance;
+                    queue.push_back((nx as usize, ny as usize));
+                }
+            }
+        }
+
+        for y in bounds.1..bounds.3 {
+            for x in bounds.0..bounds.2 {
+                self.map[(x, y)] = distances[index(x, y)];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::clearance::Clearance;
+    use crate::entity::astar::Search;
+    use crate::entity::grid::Grid;
+    use rand::Rng;
+
+    #[test]
+    fn update_test() {
+        let mut rng = rand::thread_rng();
+        let mut costs = Grid::new(30, 20, 1);
+        let mut clearance = Clearance::new(&costs, 4);
+        assert_eq!(1, clearance.get((0, 10)));
+        assert_eq!(4, clearance.get((10, 10)));
+
+        for _ in 0..20 {
+            let changes: Vec<_> = (0..5)
+                .map(|_| (rng.gen_range(0, 30), rng.gen_range(0, 20), if rng.gen() {-1} else {1}))
+                .collect();
+            for c in changes.iter() {
+                costs[(c.0, c.1)] = c.2;
+            }
+            let old = clearance.map().clone();
+            let changed = clearance.update(&costs, &changes);
+
+            let fresh = Clearance::new(&costs, 4);
+            assert_eq!(fresh.map(), clearance.map());
+            for c in changed.iter() {
+                assert_eq!(old[(c.0, c.1)], c.2);
+            }
+        }
+    }
+
+    #[test]
+    fn find_sized_test() {
+        let mut costs = Grid::new(20, 20, 1);
+        for y in 0..20 {
+            if y != 5 && !(14..=16).contains(&y) {
+                costs[(10, y)] = -1;
+            }
+        }
+        let clearance = Clearance::new(&costs, 3);
+        let search = Search::new(true);
+
+        let small = search.find_sized((2, 5), (17, 5), &costs, &clearance, Clearance::required(1));
+        assert!(small.contains(&(10, 5)));
+        let big = search.find_sized((2, 5), (17, 5), &costs, &clearance, Clearance::required(2));
+        assert!(big.contains(&(10, 15)));
+        assert!(search.find_sized((2, 5), (17, 5), &costs, &clearance, Clearance::required(4)).is_empty());
+    }
+}
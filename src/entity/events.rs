@@ -0,0 +1,139 @@
+use std::marker::PhantomData;
+
+/// Events is typed queue of events that lives for two frames. Producers `send` during the frame
+/// and `update` is called once per frame, events then stay readable for one more frame so
+/// consumer running before producer in the frame order still gets them. Buffers are reused so
+/// steady stream of events does not allocate.
+///
+/// Any number of consumers can read the events, each with own `EventReader` that remembers
+/// what it already read. Systems of `Schedule` share events by storing them as World resource.
+pub struct Events<E> {
+    /// front holds events of previous frame
+    front: Vec<E>,
+    /// back holds events of current frame
+    back: Vec<E>,
+    /// front_start is sequence number of first event in front
+    front_start: usize,
+    /// count is sequence number of next event
+    count: usize,
+}
+
+impl<E> Events<E> {
+    pub fn new() -> Self {
+        Self { front: vec![], back: vec![], front_start: 0, count: 0 }
+    }
+
+    #[inline]
+    pub fn send(&mut self, event: E) {
+        self.back.push(event);
+        self.count += 1;
+    }
+
+    /// update starts new frame, events of frame before previous are dropped
+    pub fn update(&mut self) {
+        std::mem::swap(&mut self.front, &mut self.back);
+        self.back.clear();
+        self.front_start = self.count - self.front.len();
+    }
+
+    /// reader creates reader that reads only events sent from now on
+    #[inline]
+    pub fn reader(&self) -> EventReader<E> {
+        EventReader { cursor: self.count, marker: PhantomData }
+    }
+
+    /// read iterates over events reader did not read yet and marks them as read, events
+    /// dropped before reader got to them are skipped
+    pub fn read(&self, reader: &mut EventReader<E>) -> impl Iterator<Item = &E> {
+        let start = reader.cursor.max(self.front_start) - self.front_start;
+        reader.cursor = self.count;
+        let (front, back) = if start < self.front.len() {
+            (&self.front[start..], &self.back[..])
+        } else {
+            (&self.front[..0], &self.back[start - self.front.len()..])
+        };
+        front.iter().chain(back.iter())
+    }
+
+    /// unread returns count of events reader did not read yet
+    #[inline]
+    pub fn unread(&self, reader: &EventReader<E>) -> usize {
+        self.count - reader.cursor.max(self.front_start)
+    }
+
+    /// drain removes all events and returns them from the oldest, readers will skip them
+    pub fn drain(&mut self) -> impl Iterator<Item = E> + '_ {
+        self.front_start = self.count;
+        self.front.drain(..).chain(self.back.drain(..))
+    }
+
+    /// len returns count of events of both frames
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.front.len() + self.back.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.front.clear();
+        self.back.clear();
+        self.front_start = self.count;
+    }
+}
+
+impl<E> Default for Events<E> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// EventReader is cursor of one consumer into Events
+pub struct EventReader<E> {
+    cursor: usize,
+    marker: PhantomData<fn() -> E>,
+}
+
+impl<E> Clone for EventReader<E> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self { cursor: self.cursor, marker: PhantomData }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::events::Events;
+
+    #[test]
+    fn read_test() {
+        let mut events = Events::new();
+        let mut early = events.reader();
+        events.send(1);
+        events.send(2);
+        let mut late = events.reader();
+
+        assert_eq!(vec![1, 2], events.read(&mut early).cloned().collect::<Vec<_>>());
+        events.update();
+        events.send(3);
+        assert_eq!(vec![3], events.read(&mut early).cloned().collect::<Vec<_>>());
+        assert_eq!(1, events.unread(&late));
+        assert_eq!(vec![3], events.read(&mut late).cloned().collect::<Vec<_>>());
+
+        let mut slow = early.clone();
+        events.update();
+        events.send(4);
+        events.update();
+        assert_eq!(1, events.unread(&slow));
+        assert_eq!(vec![4], events.read(&mut slow).cloned().collect::<Vec<_>>());
+        assert_eq!(0, events.read(&mut slow).count());
+
+        events.send(5);
+        assert_eq!(vec![4, 5], events.drain().collect::<Vec<_>>());
+        assert_eq!(0, events.read(&mut early).count());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::entity::grid::Grid;
+use crate::entity::{FastHash, gen_hash};
+use std::collections::HashSet;
+
+/// OCTANTS are transformations of octant coordinates to grid coordinates as (xx, xy, yx, yy)
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1), (0, 1, 1, 0), (0, -1, 1, 0), (-1, 0, 0, 1),
+    (-1, 0, 0, -1), (0, -1, -1, 0), (0, 1, -1, 0), (1, 0, 0, -1),
+];
+
+/// opacity derives opacity grid from costs in PathFinder format, impassable tiles block sight
+pub fn opacity(costs: &Grid<i32>) -> Grid<bool> {
+    let (w, h) = costs.size();
+    Grid::from_fn(w, h, |pos| costs[pos] == -1)
+}
+
+/// shadowcast calls f with every tile visible from origin within radius, radius is euclidean
+/// distance in tiles. Opaque tiles are visible themselves but hide everything behind them,
+/// outside of the grid counts as opaque. Some tiles on octant borders are reported twice.
+pub fn shadowcast<F: FnMut((usize, usize))>(opaque: &Grid<bool>, origin: (usize, usize), radius: usize, mut f: F) {
+    if opaque.get(origin).is_none() {
+        return;
+    }
+    f(origin);
+    let mut caster = Caster { opaque, origin: (origin.0 as i32, origin.1 as i32), radius: radius as i32, f };
+    for octant in OCTANTS.iter() {
+        caster.cast(1, 1f32, 0f32, *octant);
+    }
+}
+
+/// visible returns set of tiles visible from origin within radius, see `shadowcast`
+pub fn visible(opaque: &Grid<bool>, origin: (usize, usize), radius: usize) -> HashSet<(usize, usize), FastHash> {
+    let mut set = HashSet::with_hasher(gen_hash());
+    shadowcast(opaque, origin, radius, |pos| {
+        set.insert(pos);
+    });
+    set
+}
+
+/// Caster holds state shared by all recursive scans of one shadowcast
+struct Caster<'a, F> {
+    opaque: &'a Grid<bool>,
+    origin: (i32, i32),
+    radius: i32,
+    f: F,
+}
+
+impl<F: FnMut((usize, usize))> Caster<'_, F> {
+    /// cast scans one octant row by row starting at row, start and end are slopes of the light
+    /// cone still not covered by obstacles, every obstacle splits the cone and the part before
+    /// it is scanned recursively
+    fn cast(&mut self, row: i32, mut start: f32, end: f32, octant: (i32, i32, i32, i32)) {
+        if start < end {
+            return;
+        }
+        let (xx, xy, yx, yy) = octant;
+        let r2 = self.radius * self.radius;
+        let mut next_start = start;
+        for j in row..=self.radius {
+            let mut blocked = false;
+            let dy = -j;
+            for dx in -j..=0 {
+                let left = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let right = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < right {
+                    continue;
+                }
+                if end > left {
+                    break;
+                }
+
+                let x = self.origin.0 + dx * xx + dy * xy;
+                let y = self.origin.1 + dx * yx + dy * yy;
+                let wall = match self.opaque.get_signed(x, y) {
+                    Some(&wall) => {
+                        if dx * dx + dy * dy <= r2 {
+                            (self.f)((x as usize, y as usize));
+                        }
+                        wall
+                    },
+                    None => true,
+                };
+
+                if blocked {
+                    if wall {
+                        next_start = right;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if wall && j < self.radius {
+                    blocked = true;
+                    self.cast(j + 1, start, left, octant);
+                    next_start = right;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+/// Fog is fog of war shared by many units. Every frame starts with `begin` and each unit then
+/// reveals what it sees, tiles seen in any earlier frame stay explored. Clearing the visibility
+/// is just increment of frame counter so frames cost only the revealed tiles.
+pub struct Fog {
+    seen: Grid<u32>,
+    frame: u32,
+}
+
+impl Fog {
+    pub fn new(w: usize, h: usize) -> Self {
+        Self { seen: Grid::new(w, h, 0), frame: 1 }
+    }
+
+    /// begin starts new frame, all tiles stop being visible
+    #[inline]
+    pub fn begin(&mut self) {
+        self.frame += 1;
+    }
+
+    /// reveal marks tiles visible from origin within radius in current frame
+    pub fn reveal(&mut self, opaque: &Grid<bool>, origin: (usize, usize), radius: usize) {
+        let Self { seen, frame } = self;
+        shadowcast(opaque, origin, radius, |pos| seen[pos] = *frame);
+    }
+
+    /// reveal_all reveals from every (origin, radius) pair
+    pub fn reveal_all(&mut self, opaque: &Grid<bool>, units: &[((usize, usize), usize)]) {
+        for &(origin, radius) in units.iter() {
+            self.reveal(opaque, origin, radius);
+        }
+    }
+
+    /// is_visible returns whether tile was revealed in current frame
+    #[inline]
+    pub fn is_visible(&self, pos: (usize, usize)) -> bool {
+        self.seen[pos] == self.frame
+    }
+
+    /// is_explored returns whether tile was ever revealed
+    #[inline]
+    pub fn is_explored(&self, pos: (usize, usize)) -> bool {
+        self.seen[pos] != 0
+    }
+
+    /// clear forgets everything that was explored
+    pub fn clear(&mut self) {
+        self.seen.fill(0);
+        self.frame = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::fov::{visible, Fog};
+    use crate::entity::grid::Grid;
+
+    #[test]
+    fn visible_test() {
+        let mut opaque = Grid::new(20, 20, false);
+        opaque[(12, 10)] = true;
+
+        let set = visible(&opaque, (10, 10), 5);
+        assert!(set.contains(&(10, 10)));
+        assert!(set.contains(&(12, 10)));
+        assert!(!set.contains(&(13, 10)) && !set.contains(&(15, 10)));
+        assert!(set.contains(&(10, 15)) && set.contains(&(5, 10)));
+        assert!(!set.contains(&(10, 16)) && !set.contains(&(14, 14)));
+        for &(x, y) in set.iter() {
+            let (dx, dy) = (x as i32 - 10, y as i32 - 10);
+            assert!(dx * dx + dy * dy <= 25);
+        }
+    }
+
+    #[test]
+    fn fog_test() {
+        let mut opaque = Grid::new(30, 10, false);
+        for y in 0..10 {
+            opaque[(15, y)] = true;
+        }
+        let mut fog = Fog::new(30, 10);
+        fog.reveal_all(&opaque, &[((10, 5), 8), ((25, 5), 3)]);
+        assert!(fog.is_visible((5, 5)) && fog.is_visible((15, 5)) && fog.is_visible((24, 5)));
+        assert!(!fog.is_visible((16, 5)) && !fog.is_visible((17, 7)) && !fog.is_visible((20, 5)));
+
+        fog.begin();
+        fog.reveal(&opaque, (25, 5), 3);
+        assert!(!fog.is_visible((5, 5)) && fog.is_explored((5, 5)));
+        assert!(!fog.is_explored((20, 5)));
+    }
+}
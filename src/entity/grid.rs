@@ -0,0 +1,399 @@
+use crate::math::vect::Vect;
+use std::ops::{Index, IndexMut};
+
+pub const D4: [(i32, i32); 4] = [(0, 1), (1, 0), (-1, 0), (0, -1),];
+pub const D8: [(i32, i32); 8] = [(0, 1), (1, 0), (-1, 0), (0, -1), (1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+/// Grid is two dimensional array stored row by row in one flat vector, tile (x, y) lives at
+/// index y * w + x. It is indexed by `(x, y)` tuples or by Vect whose coordinates are floored,
+/// indexing outside of the grid panics, negative Vect coordinates included.
+#[derive(Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Raw<T>"))]
+pub struct Grid<T> {
+    data: Vec<T>,
+    w: usize,
+    h: usize,
+}
+
+impl<T: Clone> Grid<T> {
+    /// new creates grid filled with value
+    #[inline]
+    pub fn new(w: usize, h: usize, value: T) -> Self {
+        Self { data: vec![value; w * h], w, h }
+    }
+
+    /// fill sets all tiles to value
+    #[inline]
+    pub fn fill(&mut self, value: T) {
+        for tile in self.data.iter_mut() {
+            *tile = value.clone();
+        }
+    }
+
+    /// to_rows copies grid to nested vectors in `rows[y][x]` format
+    pub fn to_rows(&self) -> Vec<Vec<T>> {
+        self.rows().map(|row| row.to_vec()).collect()
+    }
+}
+
+impl<T> Grid<T> {
+    /// from_fn creates grid where every tile is result of f called with its position
+    pub fn from_fn<F: FnMut((usize, usize)) -> T>(w: usize, h: usize, mut f: F) -> Self {
+        let mut data = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                data.push(f((x, y)));
+            }
+        }
+        Self { data, w, h }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.w
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.h
+    }
+
+    /// size returns (width, height)
+    #[inline]
+    pub fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// contains returns whether position lies inside of the grid
+    #[inline]
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.w && (y as usize) < self.h
+    }
+
+    /// get returns tile or None if position is outside
+    #[inline]
+    pub fn get(&self, pos: (usize, usize)) -> Option<&T> {
+        if pos.0 < self.w && pos.1 < self.h {
+            Some(&self.data[pos.1 * self.w + pos.0])
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, pos: (usize, usize)) -> Option<&mut T> {
+        if pos.0 < self.w && pos.1 < self.h {
+            Some(&mut self.data[pos.1 * self.w + pos.0])
+        } else {
+            None
+        }
+    }
+
+    /// get_signed is get that accepts negative coordinates
+    #[inline]
+    pub fn get_signed(&self, x: i32, y: i32) -> Option<&T> {
+        if self.contains(x, y) {
+            Some(&self.data[y as usize * self.w + x as usize])
+        } else {
+            None
+        }
+    }
+
+    /// tile_of returns tile that contains the point, None if it is outside
+    #[inline]
+    pub fn tile_of(&self, pos: Vect) -> Option<(usize, usize)> {
+        // comparisons also reject NaN
+        if pos.x >= 0f32 && pos.y >= 0f32 && pos.x < self.w as f32 && pos.y < self.h as f32 {
+            Some((pos.x as usize, pos.y as usize))
+        } else {
+            None
+        }
+    }
+
+    /// get_vect returns tile containing the point or None if it is outside
+    #[inline]
+    pub fn get_vect(&self, pos: Vect) -> Option<&T> {
+        self.tile_of(pos).map(|tile| &self[tile])
+    }
+
+    #[inline]
+    pub fn get_vect_mut(&mut self, pos: Vect) -> Option<&mut T> {
+        let tile = self.tile_of(pos)?;
+        Some(&mut self[tile])
+    }
+
+    /// offset returns position moved by d if it stays inside of the grid
+    #[inline]
+    pub fn offset(&self, pos: (usize, usize), d: (i32, i32)) -> Option<(usize, usize)> {
+        let (x, y) = (pos.0 as i32 + d.0, pos.1 as i32 + d.1);
+        if self.contains(x, y) {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+
+    /// neighbours iterates over positions in given directions from pos that are inside
+    #[inline]
+    pub fn neighbours<'a>(&'a self, pos: (usize, usize), directions: &'a [(i32, i32)]) -> impl Iterator<Item = (usize, usize)> + 'a {
+        directions.iter().filter_map(move |d| self.offset(pos, *d))
+    }
+
+    /// d4 iterates over orthogonal neighbours of pos
+    #[inline]
+    pub fn d4(&self, pos: (usize, usize)) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.neighbours(pos, &D4)
+    }
+
+    /// d8 iterates over orthogonal and diagonal neighbours of pos
+    #[inline]
+    pub fn d8(&self, pos: (usize, usize)) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.neighbours(pos, &D8)
+    }
+
+    /// index_of returns index of position in flat storage
+    #[inline]
+    pub fn index_of(&self, pos: (usize, usize)) -> usize {
+        pos.1 * self.w + pos.0
+    }
+
+    /// pos_of returns position of index in flat storage
+    #[inline]
+    pub fn pos_of(&self, index: usize) -> (usize, usize) {
+        (index % self.w, index / self.w)
+    }
+
+    #[inline]
+    pub fn row(&self, y: usize) -> &[T] {
+        &self.data[y * self.w..(y + 1) * self.w]
+    }
+
+    #[inline]
+    pub fn row_mut(&mut self, y: usize) -> &mut [T] {
+        &mut self.data[y * self.w..(y + 1) * self.w]
+    }
+
+    /// rows iterates over rows from y = 0
+    #[inline]
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.data.chunks(self.w.max(1))
+    }
+
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    #[inline]
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.data.iter_mut()
+    }
+
+    /// positions iterates over all positions row by row
+    #[inline]
+    pub fn positions(&self) -> impl Iterator<Item = (usize, usize)> {
+        let w = self.w;
+        (0..self.data.len()).map(move |i| (i % w, i / w))
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    #[inline]
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+
+    /// view returns read only window to the region of grid given as (min x, min y, max x,
+    /// max y) with max exclusive, region is clamped to the grid
+    pub fn view(&self, bounds: (usize, usize, usize, usize)) -> View<'_, T> {
+        let max = (bounds.2.min(self.w), bounds.3.min(self.h));
+        let min = (bounds.0.min(max.0), bounds.1.min(max.1));
+        View { grid: self, origin: min, w: max.0 - min.0, h: max.1 - min.1 }
+    }
+}
+
+impl<T: Clone> Clone for Grid<T> {
+    fn clone(&self) -> Self {
+        Self { data: self.data.clone(), w: self.w, h: self.h }
+    }
+
+    /// clone_from reuses allocation so copying field of the same size is just a memcpy
+    fn clone_from(&mut self, source: &Self) {
+        self.data.clone_from(&source.data);
+        self.w = source.w;
+        self.h = source.h;
+    }
+}
+
+impl<T> Index<(usize, usize)> for Grid<T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, pos: (usize, usize)) -> &T {
+        assert!(pos.0 < self.w && pos.1 < self.h, "position {:?} is outside of {}x{} grid", pos, self.w, self.h);
+        &self.data[pos.1 * self.w + pos.0]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for Grid<T> {
+    #[inline]
+    fn index_mut(&mut self, pos: (usize, usize)) -> &mut T {
+        assert!(pos.0 < self.w && pos.1 < self.h, "position {:?} is outside of {}x{} grid", pos, self.w, self.h);
+        &mut self.data[pos.1 * self.w + pos.0]
+    }
+}
+
+impl<T> Index<Vect> for Grid<T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, pos: Vect) -> &T {
+        let (w, h) = (self.w, self.h);
+        self.get_vect(pos).unwrap_or_else(|| panic!("position {:?} is outside of {}x{} grid", pos, w, h))
+    }
+}
+
+impl<T> IndexMut<Vect> for Grid<T> {
+    #[inline]
+    fn index_mut(&mut self, pos: Vect) -> &mut T {
+        let (w, h) = (self.w, self.h);
+        self.get_vect_mut(pos).unwrap_or_else(|| panic!("position {:?} is outside of {}x{} grid", pos, w, h))
+    }
+}
+
+/// from converts nested vectors in `rows[y][x]` format, all rows has to be equally long
+impl<T> From<Vec<Vec<T>>> for Grid<T> {
+    fn from(rows: Vec<Vec<T>>) -> Self {
+        let h = rows.len();
+        let w = rows.first().map_or(0, |r| r.len());
+        assert!(rows.iter().all(|r| r.len() == w), "rows of grid has to be equally long");
+        Self { data: rows.into_iter().flatten().collect(), w, h }
+    }
+}
+
+/// Raw is Grid as it is deserialized, before its size is checked
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct Raw<T> {
+    data: Vec<T>,
+    w: usize,
+    h: usize,
+}
+
+#[cfg(feature = "serde")]
+impl<T> std::convert::TryFrom<Raw<T>> for Grid<T> {
+    type Error = String;
+
+    fn try_from(raw: Raw<T>) -> Result<Self, Self::Error> {
+        if raw.w.checked_mul(raw.h) != Some(raw.data.len()) {
+            return Err(format!("grid of size {}x{} cannot have {} tiles", raw.w, raw.h, raw.data.len()));
+        }
+        Ok(Self { data: raw.data, w: raw.w, h: raw.h })
+    }
+}
+
+/// View is read only rectangular window to a grid, positions are relative to its origin
+#[derive(Copy, Clone)]
+pub struct View<'a, T> {
+    grid: &'a Grid<T>,
+    origin: (usize, usize),
+    w: usize,
+    h: usize,
+}
+
+impl<'a, T> View<'a, T> {
+    #[inline]
+    pub fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+
+    /// origin returns position of views (0, 0) in the grid
+    #[inline]
+    pub fn origin(&self) -> (usize, usize) {
+        self.origin
+    }
+
+    #[inline]
+    pub fn get(&self, pos: (usize, usize)) -> Option<&'a T> {
+        if pos.0 < self.w && pos.1 < self.h {
+            self.grid.get((self.origin.0 + pos.0, self.origin.1 + pos.1))
+        } else {
+            None
+        }
+    }
+
+    /// row returns part of grid row that is inside of view
+    #[inline]
+    pub fn row(&self, y: usize) -> &'a [T] {
+        &self.grid.row(self.origin.1 + y)[self.origin.0..self.origin.0 + self.w]
+    }
+
+    /// iter iterates over grid positions inside of view together with tiles
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), &'a T)> + 'a {
+        let (origin, w, h, grid) = (self.origin, self.w, self.h, self.grid);
+        (0..w * h).map(move |i| {
+            let pos = (origin.0 + i % w, origin.1 + i / w);
+            (pos, &grid[pos])
+        })
+    }
+}
+
+impl<T> Index<(usize, usize)> for View<'_, T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, pos: (usize, usize)) -> &T {
+        self.get(pos).expect("position is outside of view")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::grid::Grid;
+    use crate::math::vect::Vect;
+
+    #[test]
+    fn grid_test() {
+        let mut grid = Grid::from(vec![vec![1, 2, 3], vec![4, 5, 6]]);
+        assert_eq!((3, 2), grid.size());
+        assert_eq!(6, grid[(2, 1)]);
+        assert_eq!(5, grid[Vect::new(1.5, 1.9)]);
+        assert_eq!(None, grid.get_vect(Vect::new(-5.0, 1.0)));
+        assert_eq!(None, grid.get_vect(Vect::new(3.0, 0.0)));
+        assert_eq!(None, grid.get((3, 0)));
+        assert_eq!(None, grid.get_signed(-1, 0));
+        grid[(0, 1)] = 10;
+        assert_eq!(vec![vec![1, 2, 3], vec![10, 5, 6]], grid.to_rows());
+
+        let mut neighbours: Vec<_> = grid.d8((0, 0)).collect();
+        neighbours.sort();
+        assert_eq!(vec![(0, 1), (1, 0), (1, 1)], neighbours);
+
+        let view = grid.view((1, 0, 5, 2));
+        assert_eq!((2, 2), view.size());
+        assert_eq!(5, view[(0, 1)]);
+        assert_eq!(&[5, 6], view.row(1));
+        assert_eq!(vec![2, 3, 5, 6], view.iter().map(|t| *t.1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_index_test() {
+        let grid = Grid::new(3, 3, 0);
+        let _ = grid[Vect::new(-5.0, 1.0)];
+    }
+}
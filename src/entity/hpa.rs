@@ -0,0 +1,408 @@
+use crate::entity::astar::{Search, Heuristic, Bounds, STRAIGHT, DIAGONAL, neighbours, inside, min_cost};
+use crate::entity::grid::Grid;
+use crate::entity::{FastHash, gen_hash};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::cmp::Reverse;
+
+struct Node {
+    pos: (usize, usize),
+    cluster: usize,
+    edges: Vec<(usize, i32)>,
+}
+
+/// Hierarchy is HPA* layer over cost grid in PathFinder format. Grid is split to square clusters,
+/// tiles where two clusters can be crossed become entrance nodes and distances between entrances
+/// of one cluster are precomputed. Long queries are answered on this small abstract graph and
+/// refined locally, so they are many times cheaper then full search. Paths are near optimal,
+/// usually within few percent of the shortest path.
+pub struct Hierarchy {
+    costs: Grid<i32>,
+    search: Search,
+    cluster_size: usize,
+    clusters: (usize, usize),
+    w: usize,
+    h: usize,
+    min_cost: i32,
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+    cluster_nodes: Vec<Vec<usize>>,
+    borders: HashMap<(usize, usize), Vec<usize>, FastHash>,
+}
+
+impl Hierarchy {
+    /// new builds the hierarchy, bigger clusters mean smaller abstract graph but more expensive
+    /// refinement and updates
+    pub fn new(costs: &Grid<i32>, cluster_size: usize, diagonal: bool) -> Self {
+        let (w, h) = costs.size();
+        let clusters = (w.div_ceil(cluster_size), h.div_ceil(cluster_size));
+        let mut hierarchy = Self {
+            costs: costs.clone(),
+            search: Search::new(diagonal),
+            cluster_size,
+            clusters,
+            w,
+            h,
+            min_cost: min_cost(costs),
+            nodes: vec![],
+            free: vec![],
+            cluster_nodes: vec![vec![]; clusters.0 * clusters.1],
+            borders: HashMap::with_hasher(gen_hash()),
+        };
+
+        for c in 0..clusters.0 * clusters.1 {
+            for n in hierarchy.neighbour_clusters(c) {
+                if c < n {
+                    hierarchy.build_border(c, n);
+                }
+            }
+        }
+        for c in 0..clusters.0 * clusters.1 {
+            hierarchy.build_cluster(c);
+        }
+
+        hierarchy
+    }
+
+    /// update applies changes in the same format as `PathFinder::update` does and rebuilds
+    /// only clusters and borders touched by them
+    pub fn update(&mut self, changes: &[(usize, usize, i32)]) {
+        let mut clusters = HashSet::with_hasher(gen_hash());
+        let mut borders = HashSet::with_hasher(gen_hash());
+        let mut rescan = false;
+
+        for &(x, y, cost) in changes.iter() {
+            let old = std::mem::replace(&mut self.costs[(x, y)], cost);
+            // only tile holding the minimum getting more expensive can raise it
+            if old == self.min_cost && (cost == -1 || cost > old) {
+                rescan = true;
+            } else if cost != -1 && cost < self.min_cost {
+                self.min_cost = cost;
+            }
+            let c = self.cluster_of((x, y));
+            clusters.insert(c);
+            for n in self.neighbour_clusters(c) {
+                let b = self.bounds(n);
+                if x + 1 == b.0 || x == b.2 || y + 1 == b.1 || y == b.3 {
+                    borders.insert((c.min(n), c.max(n)));
+                    clusters.insert(n);
+                }
+            }
+        }
+
+        if rescan {
+            self.min_cost = min_cost(&self.costs);
+        }
+
+        for (a, b) in borders {
+            self.build_border(a, b);
+        }
+        for c in clusters {
+            self.build_cluster(c);
+        }
+    }
+
+    /// costs returns current cost grid
+    #[inline]
+    pub fn costs(&self) -> &Grid<i32> {
+        &self.costs
+    }
+
+    /// node_count returns count of abstract nodes
+    #[inline]
+    pub fn node_count(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    /// find_path returns path from start to goal including both, empty if there is none
+    pub fn find_path(&self, start: (usize, usize), goal: (usize, usize)) -> Vec<(usize, usize)> {
+        let full = (0, 0, self.w, self.h);
+        if !inside(start, full) || !inside(goal, full) || self.costs[start] == -1 || self.costs[goal] == -1 {
+            return vec![];
+        }
+
+        let (sc, gc) = (self.cluster_of(start), self.cluster_of(goal));
+        if sc == gc {
+            if let Some((_, path)) = self.search.find_in(start, goal, &self.costs, self.bounds(sc), self.min_cost) {
+                return path;
+            }
+        }
+
+        let start_edges = self.connect(start, sc, false);
+        let goal_edges: HashMap<usize, i32, FastHash> = self.connect(goal, gc, true).into_iter().collect();
+
+        let abstract_path = match self.abstract_search(start, goal, &start_edges, &goal_edges) {
+            Some(path) => path,
+            None => return vec![],
+        };
+
+        let mut path = vec![start];
+        for window in abstract_path.windows(2) {
+            let (from, to) = (window[0], window[1]);
+            let (a, b) = (self.cluster_of(from), self.cluster_of(to));
+            if a != b {
+                path.push(to);
+                continue;
+            }
+            match self.search.find_in(from, to, &self.costs, self.bounds(a), self.min_cost) {
+                Some((_, segment)) => path.extend(segment.into_iter().skip(1)),
+                None => return vec![],
+            }
+        }
+
+        path
+    }
+
+    fn abstract_search(&self, start: (usize, usize), goal: (usize, usize), start_edges: &[(usize, i32)], goal_edges: &HashMap<usize, i32, FastHash>) -> Option<Vec<(usize, usize)>> {
+        let start_id = self.nodes.len();
+        let goal_id = start_id + 1;
+        let pos = |id: usize| if id == start_id {start} else if id == goal_id {goal} else {self.nodes[id].as_ref().unwrap().pos};
+        let heuristic = if self.search.diagonal {Heuristic::Octile} else {Heuristic::Manhattan};
+        let estimate = |id: usize| heuristic.estimate(pos(id), goal) * self.min_cost;
+
+        let mut open = BinaryHeap::new();
+        let mut visited: HashMap<usize, (i32, usize), FastHash> = HashMap::with_hasher(gen_hash());
+        visited.insert(start_id, (0, start_id));
+        open.push(Reverse((estimate(start_id), 0, start_id)));
+
+        while let Some(Reverse((_, g, current))) = open.pop() {
+            if current == goal_id {
+                let mut path = vec![goal];
+                let mut id = goal_id;
+                while id != start_id {
+                    id = visited[&id].1;
+                    path.push(pos(id));
+                }
+                path.reverse();
+                path.dedup();
+                return Some(path);
+            }
+            if g > visited[&current].0 {
+                continue;
+            }
+
+            let edges: &[(usize, i32)] = if current == start_id {
+                start_edges
+            } else {
+                &self.nodes[current].as_ref().unwrap().edges
+            };
+            let last = goal_edges.get(&current).map(|cost| (goal_id, *cost));
+
+            for &(next, cost) in edges.iter().chain(last.iter()) {
+                let supposed = g + cost;
+                if visited.get(&next).is_some_and(|v| v.0 <= supposed) {
+                    continue;
+                }
+                visited.insert(next, (supposed, current));
+                open.push(Reverse((supposed + estimate(next), supposed, next)));
+            }
+        }
+
+        None
+    }
+
+    /// connect returns costs between pos and all entrances of cluster, from pos to entrances or
+    /// from entrances to pos if reverse
+    fn connect(&self, pos: (usize, usize), cluster: usize, reverse: bool) -> Vec<(usize, i32)> {
+        let distances = self.dijkstra(pos, self.bounds(cluster), reverse);
+        self.cluster_nodes[cluster].iter()
+            .filter_map(|id| distances.get(&self.nodes[*id].as_ref().unwrap().pos).map(|d| (*id, *d)))
+            .collect()
+    }
+
+    /// dijkstra returns distances from pos to all reachable tiles inside bounds, if reverse it
+    /// returns distances from tiles to pos instead
+    fn dijkstra(&self, pos: (usize, usize), bounds: Bounds, reverse: bool) -> HashMap<(usize, usize), i32, FastHash> {
+        let mut distances = HashMap::with_hasher(gen_hash());
+        let mut open = BinaryHeap::new();
+        distances.insert(pos, 0);
+        open.push(Reverse((0, pos)));
+
+        while let Some(Reverse((g, current))) = open.pop() {
+            if g > distances[&current] {
+                continue;
+            }
+            neighbours(&self.costs, current, self.search.diagonal, bounds, |next, cost| {
+                let cost = if reverse {
+                    let straight = next.0 == current.0 || next.1 == current.1;
+                    self.costs[current] * if straight {STRAIGHT} else {DIAGONAL}
+                } else {
+                    cost
+                };
+                let supposed = g + cost;
+                if distances.get(&next).is_some_and(|d| *d <= supposed) {
+                    return;
+                }
+                distances.insert(next, supposed);
+                open.push(Reverse((supposed, next)));
+            });
+        }
+
+        distances
+    }
+
+    #[inline]
+    fn cluster_of(&self, pos: (usize, usize)) -> usize {
+        pos.1 / self.cluster_size * self.clusters.0 + pos.0 / self.cluster_size
+    }
+
+    #[inline]
+    fn bounds(&self, cluster: usize) -> Bounds {
+        let x = cluster % self.clusters.0 * self.cluster_size;
+        let y = cluster / self.clusters.0 * self.cluster_size;
+        (x, y, (x + self.cluster_size).min(self.w), (y + self.cluster_size).min(self.h))
+    }
+
+    fn neighbour_clusters(&self, cluster: usize) -> Vec<usize> {
+        let (x, y) = (cluster % self.clusters.0, cluster / self.clusters.0);
+        let mut result = vec![];
+        if x > 0 {
+            result.push(cluster - 1);
+        }
+        if x + 1 < self.clusters.0 {
+            result.push(cluster + 1);
+        }
+        if y > 0 {
+            result.push(cluster - self.clusters.0);
+        }
+        if y + 1 < self.clusters.1 {
+            result.push(cluster + self.clusters.0);
+        }
+        result
+    }
+
+    fn add_node(&mut self, pos: (usize, usize), cluster: usize) -> usize {
+        let node = Some(Node { pos, cluster, edges: vec![] });
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.cluster_nodes[cluster].push(id);
+        id
+    }
+
+    /// build_border replaces entrances between clusters a and b, a has to be lower
+    fn build_border(&mut self, a: usize, b: usize) {
+        let removed = self.borders.remove(&(a, b)).unwrap_or_default();
+        for &id in removed.iter() {
+            let cluster = self.nodes[id].take().unwrap().cluster;
+            self.cluster_nodes[cluster].retain(|n| *n != id);
+            self.free.push(id);
+        }
+        for &c in [a, b].iter() {
+            for &id in self.cluster_nodes[c].iter() {
+                self.nodes[id].as_mut().unwrap().edges.retain(|e| !removed.contains(&e.0));
+            }
+        }
+
+        let (ba, bb) = (self.bounds(a), self.bounds(b));
+        let pairs: Vec<((usize, usize), (usize, usize))> = if b == a + 1 {
+            (ba.1..ba.3).map(|y| ((ba.2 - 1, y), (bb.0, y))).collect()
+        } else {
+            (ba.0..ba.2).map(|x| ((x, ba.3 - 1), (x, bb.1))).collect()
+        };
+
+        let mut entrances = vec![];
+        let mut run = vec![];
+        for i in 0..=pairs.len() {
+            let open = i < pairs.len() && {
+                let (p, q) = pairs[i];
+                self.costs[p] != -1 && self.costs[q] != -1
+            };
+            if open {
+                run.push(pairs[i]);
+                continue;
+            }
+            if run.is_empty() {
+                continue;
+            }
+            if run.len() >= 6 {
+                entrances.push(run[0]);
+                entrances.push(run[run.len() - 1]);
+            } else {
+                entrances.push(run[run.len() / 2]);
+            }
+            run.clear();
+        }
+
+        let mut ids = vec![];
+        for (p, q) in entrances {
+            let u = self.add_node(p, a);
+            let v = self.add_node(q, b);
+            let to_v = self.costs[q] * STRAIGHT;
+            let to_u = self.costs[p] * STRAIGHT;
+            self.nodes[u].as_mut().unwrap().edges.push((v, to_v));
+            self.nodes[v].as_mut().unwrap().edges.push((u, to_u));
+            ids.push(u);
+            ids.push(v);
+        }
+        self.borders.insert((a, b), ids);
+    }
+
+    /// build_cluster recomputes distances between entrances of cluster
+    fn build_cluster(&mut self, cluster: usize) {
+        let bounds = self.bounds(cluster);
+        let ids = self.cluster_nodes[cluster].clone();
+        for &id in ids.iter() {
+            let pos = self.nodes[id].as_ref().unwrap().pos;
+            let distances = self.dijkstra(pos, bounds, false);
+            let mut edges: Vec<(usize, i32)> = ids.iter()
+                .filter(|other| **other != id)
+                .filter_map(|other| distances.get(&self.nodes[*other].as_ref().unwrap().pos).map(|d| (*other, *d)))
+                .collect();
+
+            let nodes = &self.nodes;
+            let node = self.nodes[id].as_ref().unwrap();
+            let mut kept: Vec<(usize, i32)> = node.edges.iter()
+                .filter(|e| nodes[e.0].as_ref().is_some_and(|n| n.cluster != cluster))
+                .cloned()
+                .collect();
+            kept.append(&mut edges);
+            self.nodes[id].as_mut().unwrap().edges = kept;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::hpa::Hierarchy;
+    use crate::entity::astar::{find_path, min_cost};
+    use crate::entity::grid::Grid;
+
+    #[test]
+    fn find_test() {
+        let mut costs = Grid::new(40, 40, 1);
+        for y in 0..39 {
+            costs[(20, y)] = -1;
+        }
+
+        let mut hierarchy = Hierarchy::new(&costs, 8, true);
+        let path = hierarchy.find_path((2, 2), (37, 3));
+        assert_eq!(Some(&(2, 2)), path.first());
+        assert_eq!(Some(&(37, 3)), path.last());
+        assert!(path.contains(&(20, 39)));
+        for w in path.windows(2) {
+            assert!((w[0].0 as i32 - w[1].0 as i32).abs() <= 1 && (w[0].1 as i32 - w[1].1 as i32).abs() <= 1);
+            assert!(costs[w[1]] != -1);
+        }
+        assert!(path.len() as f32 <= find_path((2, 2), (37, 3), &costs).len() as f32 * 1.2);
+
+        hierarchy.update(&[(20, 39, -1), (20, 10, 1)]);
+        let path = hierarchy.find_path((2, 2), (37, 3));
+        assert!(path.contains(&(20, 10)));
+
+        hierarchy.update(&[(20, 10, -1)]);
+        assert!(hierarchy.find_path((2, 2), (37, 3)).is_empty());
+
+        // minimum is tracked trough changes without rescanning
+        for &change in [(0, 0, 3), (5, 5, 0), (5, 5, -1), (0, 0, 2), (1, 0, 0), (1, 0, 5)].iter() {
+            hierarchy.update(&[change]);
+            assert_eq!(min_cost(hierarchy.costs()), hierarchy.min_cost);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::entity::astar::STRAIGHT;
+use crate::entity::grid::Grid;
+use crate::entity::pathfinder::{propagate, INFINITY};
+use std::collections::BinaryHeap;
+use std::cmp::Reverse;
+
+/// InfluenceMap spreads weighted sources over cost grid in PathFinder format. Influence of
+/// source drops with path distance according to decay function and ends at range, so walls
+/// block it the same way they block units. Influence of all sources is summed, negative
+/// strength can be used for enemies.
+pub struct InfluenceMap {
+    values: Grid<f32>,
+    workspace: Grid<i32>,
+    open: BinaryHeap<Reverse<(i32, (usize, usize))>>,
+    visited: Vec<((usize, usize), i32)>,
+    range: f32,
+    diagonal: bool,
+}
+
+impl InfluenceMap {
+    /// new creates empty map, range is max distance of influence in tiles of cost 1
+    pub fn new(w: usize, h: usize, range: f32, diagonal: bool) -> Self {
+        Self {
+            values: Grid::new(w, h, 0f32),
+            workspace: Grid::new(w, h, INFINITY),
+            open: BinaryHeap::new(),
+            visited: vec![],
+            range,
+            diagonal,
+        }
+    }
+
+    /// compute replaces the map with influence of sources given as (tile, strength). Decay is
+    /// called with distance from 0 to range and returns multiplier of strength, for example
+    /// `|d| 1f32 - d / range` for linear falloff.
+    pub fn compute<D: Fn(f32) -> f32>(&mut self, costs: &Grid<i32>, sources: &[((usize, usize), f32)], decay: D) {
+        self.values.fill(0f32);
+        let limit = (self.range * STRAIGHT as f32) as i32;
+        let Self { values, workspace, open, visited, diagonal, .. } = self;
+        for &(source, strength) in sources.iter() {
+            if costs[source] == -1 {
+                continue;
+            }
+
+            workspace[source] = 0;
+            open.push(Reverse((0, source)));
+            propagate(workspace, open, *diagonal, limit, |pos| costs[pos], |pos, value| {
+                visited.push((pos, value));
+                true
+            });
+
+            for (pos, value) in visited.drain(..) {
+                values[pos] += strength * decay(value as f32 / STRAIGHT as f32);
+                workspace[pos] = INFINITY;
+            }
+        }
+    }
+
+    /// add adds other map multiplied by weight to this one, maps has to be of the same size.
+    /// Subtracting threat from own influence for example gives map of safe tiles.
+    pub fn add(&mut self, other: &InfluenceMap, weight: f32) {
+        assert_eq!(self.values.size(), other.values.size(), "influence maps has to be of the same size");
+        for (value, o) in self.values.iter_mut().zip(other.values.iter()) {
+            *value += o * weight;
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, pos: (usize, usize)) -> f32 {
+        self.values[pos]
+    }
+
+    #[inline]
+    pub fn values(&self) -> &Grid<f32> {
+        &self.values
+    }
+
+    /// best_near returns passable tile with the highest influence in square of given radius
+    /// around pos, pos itself if it is the best or nothing around is passable
+    pub fn best_near(&self, costs: &Grid<i32>, pos: (usize, usize), radius: usize) -> (usize, usize) {
+        let view = self.values.view((
+            pos.0.saturating_sub(radius),
+            pos.1.saturating_sub(radius),
+            pos.0 + radius + 1,
+            pos.1 + radius + 1,
+        ));
+        let mut best = (pos, self.values[pos]);
+        for (tile, &value) in view.iter() {
+            if value > best.1 && costs[tile] != -1 {
+                best = (tile, value);
+            }
+        }
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::influence::InfluenceMap;
+    use crate::entity::grid::Grid;
+
+    #[test]
+    fn compute_test() {
+        let mut costs = Grid::new(20, 20, 1);
+        for y in 0..20 {
+            costs[(10, y)] = -1;
+        }
+        let range = 5f32;
+        let decay = |d: f32| 1f32 - d / range;
+
+        let mut own = InfluenceMap::new(20, 20, range, true);
+        own.compute(&costs, &[((5, 10), 1f32), ((8, 10), 1f32)], decay);
+        assert_eq!(1f32 + decay(3f32), own.get((5, 10)));
+        assert_eq!(0f32, own.get((11, 10)));
+        assert_eq!(0f32, own.get((5, 16)));
+
+        let mut threat = InfluenceMap::new(20, 20, range, true);
+        threat.compute(&costs, &[((7, 10), 2f32)], decay);
+        own.add(&threat, -1f32);
+        assert!(own.get((7, 10)) < 0f32);
+
+        // retreating from threat leads away from it
+        assert!(own.best_near(&costs, (6, 10), 2).0 < 6);
+        assert_eq!((9, 9), own.best_near(&costs, (9, 9), 0));
+    }
+}
@@ -1,6 +1,24 @@
+pub mod astar;
+pub mod avoidance;
+pub mod bvh;
+pub mod clearance;
+pub mod events;
+pub mod fov;
+pub mod grid;
+pub mod hpa;
+pub mod influence;
+pub mod mover;
+pub mod navmesh;
 pub mod pathfinder;
+pub mod pathservice;
+pub mod physics;
+pub mod quadtree;
 pub mod scanner;
+pub mod schedule;
+pub mod spatial;
+pub mod steering;
 pub mod storage;
+pub mod world;
 
 use std::hash::BuildHasherDefault;
 use hashers::fnv::FNV1aHasher32;
@@ -0,0 +1,164 @@
+use crate::entity::grid::Grid;
+use crate::math::rect::{Rect, Sides};
+use crate::math::vect::Vect;
+
+/// SOLID is tile value that blocks movement, same as impassable tile of PathFinder
+pub const SOLID: i32 = -1;
+
+/// Movement is result of `Mover::move_rect`
+#[derive(Copy, Clone, Debug)]
+pub struct Movement {
+    /// rect is resolved position of moved rect
+    pub rect: Rect,
+    /// x is side of rect that hit a tile when moving horizontally
+    pub x: Option<Sides>,
+    /// y is side of rect that hit a tile when moving vertically
+    pub y: Option<Sides>,
+}
+
+impl Movement {
+    /// hit returns whether given side of rect hit a tile
+    #[inline]
+    pub fn hit(&self, side: Sides) -> bool {
+        self.x == Some(side) || self.y == Some(side)
+    }
+
+    /// on_ground returns whether rect landed on something while falling
+    #[inline]
+    pub fn on_ground(&self) -> bool {
+        self.y == Some(Sides::Bottom)
+    }
+}
+
+/// Mover moves AABBs trough tile grids of the same format PathFinder uses. Rect is
+/// moved along x axis first and then along y, every tile it passes is checked so rect
+/// cannot tunnel trough walls no matter how fast it goes. Tiles outside of the grid are empty.
+#[derive(Copy, Clone, Debug)]
+pub struct Mover {
+    pub tile_size: Vect,
+    pub origin: Vect,
+    /// one_way is tile value of platforms that block only rects falling on them from above
+    pub one_way: Option<i32>,
+}
+
+impl Mover {
+    /// new creates mover for grid starting at the origin of world
+    #[inline]
+    pub fn new(tile_size: Vect) -> Self {
+        Self { tile_size, origin: Vect::ZERO, one_way: None }
+    }
+
+    /// move_rect moves rect by delta and returns where it stopped and what it hit
+    pub fn move_rect(&self, rect: &Rect, delta: Vect, tiles: &Grid<i32>) -> Movement {
+        let mut rect = rect.moved(self.origin.inverted());
+        let mut result = Movement { rect, x: None, y: None };
+
+        if delta.x != 0f32 {
+            let (min, max, hit) = self.sweep(
+                (rect.min.x, rect.max.x), (rect.min.y, rect.max.y), delta.x, false,
+                |x, y| self.is_solid(tiles, x, y, false),
+            );
+            rect.min.x = min;
+            rect.max.x = max;
+            if hit {
+                result.x = Some(if delta.x > 0f32 {Sides::Right} else {Sides::Left});
+            }
+        }
+
+        if delta.y != 0f32 {
+            let falling = delta.y < 0f32;
+            let (min, max, hit) = self.sweep(
+                (rect.min.y, rect.max.y), (rect.min.x, rect.max.x), delta.y, true,
+                |y, x| self.is_solid(tiles, x, y, falling),
+            );
+            rect.min.y = min;
+            rect.max.y = max;
+            if hit {
+                result.y = Some(if falling {Sides::Bottom} else {Sides::Top});
+            }
+        }
+
+        result.rect = rect.moved(self.origin);
+        result
+    }
+
+    #[inline]
+    fn is_solid(&self, tiles: &Grid<i32>, x: i32, y: i32, falling: bool) -> bool {
+        let tile = match tiles.get_signed(x, y) {
+            Some(&tile) => tile,
+            None => return false,
+        };
+        tile == SOLID || falling && self.one_way == Some(tile)
+    }
+
+    /// sweep moves span along one axis, other is the span on perpendicular axis, returns
+    /// new span and whether it hit a tile
+    fn sweep<F: Fn(i32, i32) -> bool>(&self, span: (f32, f32), other: (f32, f32), delta: f32, vertical: bool, solid: F) -> (f32, f32, bool) {
+        let (size, other_size) = if vertical {
+            (self.tile_size.y, self.tile_size.x)
+        } else {
+            (self.tile_size.x, self.tile_size.y)
+        };
+
+        let first = (other.0 / other_size).floor() as i32;
+        let last = (other.1 / other_size).ceil() as i32;
+        let blocked = |i: i32| (first..last.max(first + 1)).any(|j| solid(i, j));
+
+        if delta > 0f32 {
+            let from = (span.1 / size).ceil() as i32;
+            let to = ((span.1 + delta) / size).ceil() as i32;
+            for i in from..to {
+                if blocked(i) {
+                    let max = i as f32 * size;
+                    return (max - (span.1 - span.0), max, true);
+                }
+            }
+        } else {
+            let from = (span.0 / size).floor() as i32;
+            let to = ((span.0 + delta) / size).floor() as i32;
+            for i in (to..from).rev() {
+                if blocked(i) {
+                    let min = (i + 1) as f32 * size;
+                    return (min, min + (span.1 - span.0), true);
+                }
+            }
+        }
+
+        (span.0 + delta, span.1 + delta, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::mover::{Mover, SOLID};
+    use crate::entity::grid::Grid;
+    use crate::math::rect::{Rect, Sides};
+    use crate::math::vect::Vect;
+
+    #[test]
+    fn move_test() {
+        let mut tiles = Grid::new(10, 10, 0);
+        for x in 0..10 {
+            tiles[(x, 0)] = SOLID;
+        }
+        tiles[(5, 1)] = SOLID;
+        tiles[(2, 4)] = 1;
+
+        let mut mover = Mover::new(Vect::new(10f32, 10f32));
+        let rect = Rect::new(12f32, 10f32, 18f32, 18f32);
+
+        let m = mover.move_rect(&rect, Vect::new(1000f32, -5f32), &tiles);
+        assert_eq!(Rect::new(44f32, 10f32, 50f32, 18f32).min, m.rect.min);
+        assert!(m.hit(Sides::Right));
+        assert!(m.on_ground());
+
+        let m = mover.move_rect(&Rect::new(21f32, 60f32, 29f32, 70f32), Vect::new(0f32, -100f32), &tiles);
+        assert_eq!(10f32, m.rect.min.y);
+
+        mover.one_way = Some(1);
+        let m = mover.move_rect(&Rect::new(21f32, 60f32, 29f32, 70f32), Vect::new(0f32, -100f32), &tiles);
+        assert_eq!(50f32, m.rect.min.y);
+        let m = mover.move_rect(&Rect::new(21f32, 20f32, 29f32, 30f32), Vect::new(0f32, 100f32), &tiles);
+        assert_eq!(None, m.y);
+    }
+}
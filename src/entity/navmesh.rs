@@ -0,0 +1,480 @@
+use crate::entity::{FastHash, gen_hash};
+use crate::math::rect::Rect;
+use crate::math::sweep::closest_on_segment;
+use crate::math::vect::Vect;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Triangle is one cell of NavMesh, vertices are in counter clockwise order and neighbour i is
+/// triangle sharing edge from vertex i to vertex i + 1
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    pub vertices: [usize; 3],
+    pub neighbours: [Option<usize>; 3],
+    pub bounds: Rect,
+    /// component is id of group of triangles reachable from each other
+    pub component: usize,
+}
+
+/// NavMesh is triangulated walkable area of polygonal level. It is built from outer boundary
+/// and obstacles that have to lie inside of it without overlapping each other. Paths are
+/// searched over triangles with A* and then pulled tight with funnel algorithm.
+pub struct NavMesh {
+    vertices: Vec<Vect>,
+    triangles: Vec<Triangle>,
+}
+
+impl NavMesh {
+    /// new triangulates area inside boundary without obstacles, polygons can have any winding
+    pub fn new(boundary: &[Vect], obstacles: &[Vec<Vect>]) -> Self {
+        let mut vertices = boundary.to_vec();
+        let mut outer: Vec<usize> = (0..boundary.len()).collect();
+        if signed_area(&vertices, &outer) < 0f32 {
+            outer.reverse();
+        }
+
+        let mut holes = vec![];
+        for obstacle in obstacles.iter().filter(|o| o.len() >= 3) {
+            let start = vertices.len();
+            vertices.extend(obstacle);
+            let mut hole: Vec<usize> = (start..vertices.len()).collect();
+            if signed_area(&vertices, &hole) > 0f32 {
+                hole.reverse();
+            }
+            holes.push(hole);
+        }
+
+        // holes are joined from the right so bridges of later holes cannot cross earlier ones
+        holes.sort_by(|a, b| max_x(&vertices, b).total_cmp(&max_x(&vertices, a)));
+        for hole in holes.iter() {
+            bridge(&vertices, &mut outer, hole);
+        }
+
+        let triangles = triangulate(&vertices, outer);
+        let mut mesh = Self { vertices, triangles: Vec::with_capacity(triangles.len()) };
+        mesh.link(triangles);
+        mesh
+    }
+
+    #[inline]
+    pub fn vertices(&self) -> &[Vect] {
+        &self.vertices
+    }
+
+    #[inline]
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    /// locate returns triangle containing the point
+    pub fn locate(&self, pos: Vect) -> Option<usize> {
+        self.triangles.iter().position(|t| inside_rect(&t.bounds, pos) && self.contains(t, pos))
+    }
+
+    /// nearest_point returns point of the mesh closest to pos, pos itself if it is on the mesh
+    #[inline]
+    pub fn nearest_point(&self, pos: Vect) -> Option<Vect> {
+        self.nearest(pos, None).map(|n| n.1)
+    }
+
+    /// nearest_reachable returns point closest to `to` that can be reached from `from`
+    pub fn nearest_reachable(&self, from: Vect, to: Vect) -> Option<Vect> {
+        let (start, _) = self.nearest(from, None)?;
+        self.nearest(to, Some(self.triangles[start].component)).map(|n| n.1)
+    }
+
+    /// find_path returns shortest path from `from` to `to` including both points. Points outside
+    /// of the mesh are moved to the nearest point of it and if `to` cannot be reached, path
+    /// leads to the nearest reachable point instead. Path is empty only if mesh is empty.
+    pub fn find_path(&self, from: Vect, to: Vect) -> Vec<Vect> {
+        let (start, from) = match self.nearest(from, None) {
+            Some(n) => n,
+            None => return vec![],
+        };
+        let (goal, to) = self.nearest(to, Some(self.triangles[start].component)).unwrap();
+
+        let corridor = self.search(start, goal, from, to);
+        let mut portals = Vec::with_capacity(corridor.len() + 1);
+        portals.push((from, from));
+        for pair in corridor.windows(2) {
+            let t = &self.triangles[pair[0]];
+            let i = t.neighbours.iter().position(|n| *n == Some(pair[1])).unwrap();
+            let (right, left) = (t.vertices[i], t.vertices[(i + 1) % 3]);
+            portals.push((self.vertices[left], self.vertices[right]));
+        }
+        portals.push((to, to));
+
+        funnel(&portals)
+    }
+
+    /// search finds corridor of triangles from start to goal, triangles are entered trough
+    /// midpoints of shared edges
+    fn search(&self, start: usize, goal: usize, from: Vect, to: Vect) -> Vec<usize> {
+        let mut open = BinaryHeap::new();
+        let mut nodes: HashMap<usize, (f32, usize, Vect), FastHash> = HashMap::with_hasher(gen_hash());
+        nodes.insert(start, (0f32, start, from));
+        open.push(Open(from.dist(to), start));
+
+        while let Some(Open(_, current)) = open.pop() {
+            if current == goal {
+                break;
+            }
+            let (g, _, point) = nodes[&current];
+            let t = &self.triangles[current];
+            for (i, neighbour) in t.neighbours.iter().enumerate() {
+                let neighbour = match neighbour {
+                    Some(n) => *n,
+                    None => continue,
+                };
+                let entry = (self.vertices[t.vertices[i]] + self.vertices[t.vertices[(i + 1) % 3]]) / 2f32;
+                let supposed = g + point.dist(entry);
+                if nodes.get(&neighbour).is_some_and(|n| n.0 <= supposed) {
+                    continue;
+                }
+                nodes.insert(neighbour, (supposed, current, entry));
+                open.push(Open(supposed + entry.dist(to), neighbour));
+            }
+        }
+
+        let mut corridor = vec![goal];
+        let mut current = goal;
+        while current != start {
+            current = nodes[&current].1;
+            corridor.push(current);
+        }
+        corridor.reverse();
+        corridor
+    }
+
+    /// nearest returns triangle and its point closest to pos, optionally only from one component
+    fn nearest(&self, pos: Vect, component: Option<usize>) -> Option<(usize, Vect)> {
+        if let Some(t) = self.locate(pos) {
+            if component.is_none_or(|c| c == self.triangles[t].component) {
+                return Some((t, pos));
+            }
+        }
+
+        let mut best: Option<(f32, usize, Vect)> = None;
+        for (i, t) in self.triangles.iter().enumerate() {
+            if component.is_some_and(|c| c != t.component) {
+                continue;
+            }
+            for e in 0..3 {
+                let a = self.vertices[t.vertices[e]];
+                let b = self.vertices[t.vertices[(e + 1) % 3]];
+                let point = closest_on_segment(pos, a, b);
+                let dist = point.dist(pos);
+                if best.is_none_or(|b| dist < b.0) {
+                    best = Some((dist, i, point));
+                }
+            }
+        }
+        best.map(|b| (b.1, b.2))
+    }
+
+    #[inline]
+    fn contains(&self, t: &Triangle, pos: Vect) -> bool {
+        let v = t.vertices;
+        in_triangle(pos, self.vertices[v[0]], self.vertices[v[1]], self.vertices[v[2]])
+    }
+
+    /// link builds triangles with neighbours and marks connected components
+    fn link(&mut self, triangles: Vec<[usize; 3]>) {
+        let mut edges: HashMap<(usize, usize), usize, FastHash> = HashMap::with_hasher(gen_hash());
+        for (i, vertices) in triangles.iter().enumerate() {
+            let points = [self.vertices[vertices[0]], self.vertices[vertices[1]], self.vertices[vertices[2]]];
+            let mut triangle = Triangle { vertices: *vertices, neighbours: [None; 3], bounds: Rect::bounds_for(&points), component: usize::MAX };
+            for e in 0..3 {
+                let (a, b) = (vertices[e], vertices[(e + 1) % 3]);
+                if let Some(&other) = edges.get(&(b, a)) {
+                    triangle.neighbours[e] = Some(other);
+                    let t: &mut Triangle = &mut self.triangles[other];
+                    let k = (0..3).find(|k| t.vertices[*k] == b && t.vertices[(k + 1) % 3] == a).unwrap();
+                    t.neighbours[k] = Some(i);
+                }
+                edges.insert((a, b), i);
+            }
+            self.triangles.push(triangle);
+        }
+
+        let mut component = 0;
+        for i in 0..self.triangles.len() {
+            if self.triangles[i].component != usize::MAX {
+                continue;
+            }
+            let mut stack = vec![i];
+            self.triangles[i].component = component;
+            while let Some(current) = stack.pop() {
+                for n in self.triangles[current].neighbours.iter().flatten().cloned().collect::<Vec<_>>() {
+                    if self.triangles[n].component == usize::MAX {
+                        self.triangles[n].component = component;
+                        stack.push(n);
+                    }
+                }
+            }
+            component += 1;
+        }
+    }
+}
+
+/// Open is node of A* open list ordered so the lowest estimate is popped first
+#[derive(PartialEq)]
+struct Open(f32, usize);
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// funnel pulls path trough portals given as (left, right) in direction of travel, first and
+/// last portal are start and end point
+fn funnel(portals: &[(Vect, Vect)]) -> Vec<Vect> {
+    let start = portals[0].0;
+    let mut path = vec![start];
+    let (mut apex, mut left, mut right) = (start, start, start);
+    let (mut left_i, mut right_i) = (0, 0);
+
+    let mut i = 1;
+    while i < portals.len() {
+        let (l, r) = portals[i];
+
+        if area2(apex, right, r) <= 0f32 {
+            if apex == right || area2(apex, left, r) > 0f32 {
+                right = r;
+                right_i = i;
+            } else {
+                path.push(left);
+                apex = left;
+                right = apex;
+                right_i = left_i;
+                i = left_i + 1;
+                continue;
+            }
+        }
+
+        if area2(apex, left, l) >= 0f32 {
+            if apex == left || area2(apex, right, l) < 0f32 {
+                left = l;
+                left_i = i;
+            } else {
+                path.push(right);
+                apex = right;
+                left = apex;
+                left_i = right_i;
+                i = right_i + 1;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    let end = portals[portals.len() - 1].0;
+    if path.last() != Some(&end) {
+        path.push(end);
+    }
+    path
+}
+
+/// area2 is twice the signed area of triangle, it is positive when c lies right of a -> b
+#[inline]
+fn area2(a: Vect, b: Vect, c: Vect) -> f32 {
+    (c - a).cross(b - a)
+}
+
+#[inline]
+fn signed_area(vertices: &[Vect], polygon: &[usize]) -> f32 {
+    let n = polygon.len();
+    (0..n).map(|i| vertices[polygon[i]].cross(vertices[polygon[(i + 1) % n]])).sum::<f32>() / 2f32
+}
+
+#[inline]
+fn max_x(vertices: &[Vect], polygon: &[usize]) -> f32 {
+    polygon.iter().map(|v| vertices[*v].x).fold(f32::MIN, f32::max)
+}
+
+#[inline]
+fn inside_rect(rect: &Rect, pos: Vect) -> bool {
+    pos.x >= rect.min.x && pos.x <= rect.max.x && pos.y >= rect.min.y && pos.y <= rect.max.y
+}
+
+/// in_triangle returns whether p lies inside or on the edge of counter clockwise triangle
+#[inline]
+fn in_triangle(p: Vect, a: Vect, b: Vect, c: Vect) -> bool {
+    (b - a).cross(p - a) >= 0f32 && (c - b).cross(p - b) >= 0f32 && (a - c).cross(p - c) >= 0f32
+}
+
+/// bridge cuts the polygon from rightmost vertex of the hole to visible vertex of the outer
+/// polygon and splices the hole in, so polygon with holes becomes one simple polygon
+fn bridge(vertices: &[Vect], outer: &mut Vec<usize>, hole: &[usize]) {
+    let (hole_i, m) = hole.iter()
+        .enumerate()
+        .map(|(i, v)| (i, vertices[*v]))
+        .max_by(|a, b| a.1.x.total_cmp(&b.1.x))
+        .unwrap();
+
+    // closest edge hit by ray going from m to the right
+    let n = outer.len();
+    let mut hit: Option<(f32, usize)> = None;
+    for i in 0..n {
+        let (a, b) = (vertices[outer[i]], vertices[outer[(i + 1) % n]]);
+        if (a.y > m.y) == (b.y > m.y) {
+            continue;
+        }
+        let x = a.x + (m.y - a.y) / (b.y - a.y) * (b.x - a.x);
+        if x < m.x || hit.is_some_and(|h| x >= h.0) {
+            continue;
+        }
+        let candidate = if a.y == m.y {i} else if b.y == m.y || b.x > a.x {(i + 1) % n} else {i};
+        hit = Some((x, candidate));
+    }
+    let (x, mut p) = match hit {
+        Some(hit) => hit,
+        None => return,
+    };
+
+    // reflex vertex inside triangle m, hit, p would block the view, take the one closest to ray
+    let intersection = Vect::new(x, m.y);
+    let visible = vertices[outer[p]];
+    if visible != intersection {
+        let (a, b, c) = if visible.y < m.y {(m, visible, intersection)} else {(m, intersection, visible)};
+        let mut best = f32::MAX;
+        for i in 0..n {
+            let v = vertices[outer[i]];
+            let (prev, next) = (vertices[outer[(i + n - 1) % n]], vertices[outer[(i + 1) % n]]);
+            if i == p || (v - prev).cross(next - v) >= 0f32 || !in_triangle(v, a, b, c) {
+                continue;
+            }
+            let angle = (v.y - m.y).abs().atan2(v.x - m.x);
+            if angle < best {
+                best = angle;
+                p = i;
+            }
+        }
+    }
+
+    // vertex used by earlier bridge is in polygon more times, take the copy whose corner faces m
+    let vertex = outer[p];
+    if let Some(i) = (0..n).find(|i| outer[*i] == vertex && faces(vertices, outer, *i, m)) {
+        p = i;
+    }
+
+    let mut spliced = Vec::with_capacity(n + hole.len() + 2);
+    spliced.extend_from_slice(&outer[..=p]);
+    spliced.extend_from_slice(&hole[hole_i..]);
+    spliced.extend_from_slice(&hole[..=hole_i]);
+    spliced.extend_from_slice(&outer[p..]);
+    *outer = spliced;
+}
+
+/// faces returns whether point lies inside the interior angle of polygon at position i
+#[inline]
+fn faces(vertices: &[Vect], polygon: &[usize], i: usize, point: Vect) -> bool {
+    let n = polygon.len();
+    let v = vertices[polygon[i]];
+    let (prev, next) = (vertices[polygon[(i + n - 1) % n]] - v, vertices[polygon[(i + 1) % n]] - v);
+    let dir = point - v;
+    if prev.cross(next) < 0f32 {
+        next.cross(dir) > 0f32 && dir.cross(prev) > 0f32
+    } else {
+        !(prev.cross(dir) >= 0f32 && dir.cross(next) >= 0f32)
+    }
+}
+
+/// triangulate clips ears of counter clockwise polygon until only one triangle remains
+fn triangulate(vertices: &[Vect], mut polygon: Vec<usize>) -> Vec<[usize; 3]> {
+    let mut triangles = Vec::with_capacity(polygon.len());
+    while polygon.len() > 3 {
+        let n = polygon.len();
+        let corner = |i: usize| (polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]);
+        let area = |i: usize| {
+            let (a, b, c) = corner(i);
+            (vertices[b] - vertices[a]).cross(vertices[c] - vertices[b])
+        };
+
+        match (0..n).find(|i| area(*i) > 0f32 && is_ear(vertices, &polygon, corner(*i))) {
+            Some(i) => {
+                let (a, b, c) = corner(i);
+                triangles.push([a, b, c]);
+                polygon.remove(i);
+            },
+            // only degenerate vertices are left, drop one that makes no area
+            None => match (0..n).find(|i| area(*i) == 0f32) {
+                Some(i) => {
+                    polygon.remove(i);
+                },
+                None => return triangles,
+            },
+        }
+    }
+
+    if polygon.len() == 3 && signed_area(vertices, &polygon) > 0f32 {
+        triangles.push([polygon[0], polygon[1], polygon[2]]);
+    }
+    triangles
+}
+
+/// is_ear returns whether no other vertex of polygon lies in the triangle, copies of triangle
+/// vertices made by bridges are ignored
+fn is_ear(vertices: &[Vect], polygon: &[usize], (a, b, c): (usize, usize, usize)) -> bool {
+    let (pa, pb, pc) = (vertices[a], vertices[b], vertices[c]);
+    polygon.iter().all(|&v| {
+        let p = vertices[v];
+        v == a || v == b || v == c || p == pa || p == pb || p == pc || !in_triangle(p, pa, pb, pc)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::navmesh::NavMesh;
+    use crate::math::vect::Vect;
+
+    fn square(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<Vect> {
+        vec![Vect::new(x0, y0), Vect::new(x1, y0), Vect::new(x1, y1), Vect::new(x0, y1)]
+    }
+
+    #[test]
+    fn find_path_test() {
+        let obstacle = square(40f32, 10f32, 60f32, 90f32);
+        let mesh = NavMesh::new(&square(0f32, 0f32, 100f32, 100f32), &[obstacle.clone(), square(10f32, 10f32, 20f32, 20f32)]);
+
+        let area: f32 = mesh.triangles().iter().map(|t| {
+            let v = t.vertices.map(|i| mesh.vertices()[i]);
+            (v[1] - v[0]).cross(v[2] - v[0]) / 2f32
+        }).sum();
+        assert!((area - (10000f32 - 1600f32 - 100f32)).abs() < 0.1);
+
+        let path = mesh.find_path(Vect::new(30f32, 80f32), Vect::new(70f32, 80f32));
+        assert_eq!(4, path.len());
+        assert_eq!(Vect::new(40f32, 90f32), path[1]);
+        assert_eq!(Vect::new(60f32, 90f32), path[2]);
+
+        let path = mesh.find_path(Vect::new(5f32, 50f32), Vect::new(95f32, 50f32));
+        let length: f32 = path.windows(2).map(|p| p[0].dist(p[1])).sum();
+        assert!(path.iter().skip(1).take(path.len() - 2).all(|p| obstacle.contains(p)));
+        assert!(length < 135f32);
+    }
+
+    #[test]
+    fn locate_test() {
+        let mesh = NavMesh::new(&square(0f32, 0f32, 100f32, 100f32), &[square(40f32, 40f32, 60f32, 60f32)]);
+        assert!(mesh.locate(Vect::new(10f32, 10f32)).is_some());
+        assert!(mesh.locate(Vect::new(50f32, 50f32)).is_none());
+        assert!(mesh.locate(Vect::new(150f32, 50f32)).is_none());
+        assert_eq!(Some(Vect::new(60f32, 52f32)), mesh.nearest_point(Vect::new(58f32, 52f32)));
+        assert_eq!(Some(Vect::new(100f32, 50f32)), mesh.nearest_point(Vect::new(150f32, 50f32)));
+
+        assert!(mesh.triangles().iter().all(|t| t.component == 0));
+        let reachable = mesh.nearest_reachable(Vect::new(10f32, 50f32), Vect::new(50f32, 50f32)).unwrap();
+        assert!((reachable.dist(Vect::new(50f32, 50f32)) - 10f32).abs() < 0.001);
+    }
+}
@@ -1,25 +1,127 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock, Condvar};
 use std::sync::mpsc::{Sender, channel, Receiver, TryRecvError};
 use std::thread;
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Reverse;
+use crate::entity::FastHash;
+use crate::entity::astar::{STRAIGHT, DIAGONAL, smooth};
+use crate::entity::clearance::Clearance;
+use crate::entity::grid::Grid;
+use crate::math::vect::Vect;
 
 pub const INFINITY: i32 = -1;
-pub const D4: [(i32, i32); 4] = [(0, 1), (1, 0), (-1, 0), (0, -1),];
-pub const D8: [(i32, i32); 8] = [(0, 1), (1, 0), (-1, 0), (0, -1), (1, 1), (1, -1), (-1, -1), (-1, 1)];
+pub use crate::entity::grid::{D4, D8};
+
+/// POLL is count of expanded tiles between two checks of terminator
+const POLL: usize = 256;
 
 pub struct PathFinder {
     terminator: Sender<()>,
     data: Arc<Data>,
+    origin: Vect,
+    tile_size: Vect,
 }
 
 impl PathFinder {
-    pub fn new(costs: &Vec<Vec<i32>>) -> Self {
+    pub fn new(costs: &Grid<i32>) -> Self {
+        Self::with_diagonal(costs, false)
+    }
+
+    /// with_diagonal creates PathFinder that also moves diagonally, diagonal move costs
+    /// DIAGONAL / STRAIGHT times more and cannot cut corners of impassable tiles
+    pub fn with_diagonal(costs: &Grid<i32>, diagonal: bool) -> Self {
         let (terminator, t) = channel();
         PathFinder{
             terminator,
-            data: Arc::new(Data::new(costs[0].len(), costs.len(), t, costs)),
+            data: Arc::new(Data::new(t, costs, diagonal)),
+            origin: Vect::ZERO,
+            tile_size: Vect::new(1f32, 1f32),
         }
     }
 
+    /// with_space places grid into the world, tile (x, y) covers area from
+    /// origin + (x, y) * tile_size to origin + (x + 1, y + 1) * tile_size same as in Scanner
+    #[inline]
+    pub fn with_space(mut self, origin: Vect, tile_size: Vect) -> Self {
+        self.origin = origin;
+        self.tile_size = tile_size;
+        self
+    }
+
+    /// with_clearance makes the field usable for units that need given clearance, see
+    /// `Clearance::required`, tiles they do not fit on are treated as impassable. Clearance
+    /// map with given cap is maintained together with costs.
+    pub fn with_clearance(self, required: i32, cap: i32) -> Self {
+        let clearance = Clearance::new(&self.data.costs.read().unwrap(), cap.max(required));
+        *self.data.clearance.write().unwrap() = Some(clearance);
+        self.data.processor.lock().unwrap().set_clearance(required);
+        self
+    }
+
+    /// tile_at returns tile containing world position, position outside is clamped to the grid
+    #[inline]
+    pub fn tile_at(&self, pos: Vect) -> (usize, usize) {
+        let pos = self.to_grid(pos);
+        let size = self.data.size;
+        ((pos.x.floor() as i32).clamp(0, size.0 - 1) as usize, (pos.y.floor() as i32).clamp(0, size.1 - 1) as usize)
+    }
+
+    /// tile_center returns world position of tile center
+    #[inline]
+    pub fn tile_center(&self, tile: (usize, usize)) -> Vect {
+        self.origin + Vect::new(tile.0 as f32 + 0.5, tile.1 as f32 + 0.5) * self.tile_size
+    }
+
+    /// direction_at returns unit vector pointing the way towards goals from world position.
+    /// Flow of four closest tiles is blended so units moving along it turn smoothly instead
+    /// of snapping between eight directions. Zero vector is returned at goal or where no
+    /// goal is reachable.
+    pub fn direction_at(&self, pos: Vect) -> Vect {
+        (self.field().direction_at(self.to_grid(pos)) / self.tile_size).norm()
+    }
+
+    /// distance_at returns blended field distance at world position, None if no goal is reachable
+    #[inline]
+    pub fn distance_at(&self, pos: Vect) -> Option<f32> {
+        self.field().distance_at(self.to_grid(pos))
+    }
+
+    /// waypoints smooths tile path, for example one from `Search`, and returns centers of its
+    /// turning tiles in world space
+    pub fn waypoints(&self, path: &[(usize, usize)]) -> Vec<Vect> {
+        let costs = self.data.costs.read().unwrap();
+        smooth(path, &costs).into_iter().map(|tile| self.tile_center(tile)).collect()
+    }
+
+    /// path_from follows the field from world position to the goal and returns smoothed world
+    /// waypoints starting at center of the tile, empty if no goal is reachable
+    pub fn path_from(&self, pos: Vect) -> Vec<Vect> {
+        let field = self.field();
+        let mut current = self.tile_at(pos);
+        if field.map[current] == INFINITY {
+            return vec![];
+        }
+
+        let mut path = vec![current];
+        loop {
+            let next = field.get_step(current);
+            if next == current {
+                break;
+            }
+            path.push(next);
+            current = next;
+        }
+        self.waypoints(&path)
+    }
+
+    #[inline]
+    fn to_grid(&self, pos: Vect) -> Vect {
+        (pos - self.origin) / self.tile_size
+    }
+
+    /// update applies cost changes and recomputes the field on background thread. Passing empty
+    /// frontier, or the same one as last time, keeps the goals and repairs only the region
+    /// affected by changes instead of recomputing whole map.
     pub fn update(&mut self, frontier: &Vec<(usize, usize)>, changes: &Vec<(usize, usize, i32)>) {
         let mut processor = match self.data.processor.try_lock(){
             Ok(guard) => guard,
@@ -29,158 +131,807 @@ impl PathFinder {
             }
         };
         processor.frontier.extend(frontier);
+        processor.generation = self.data.published.request();
 
-        for change in changes.iter() {
-            processor.costs[change.1][change.0] = change.2;
+        let mut costs = self.data.costs.write().unwrap();
+        processor.apply(&mut costs, changes);
+        if let Some(clearance) = self.data.clearance.write().unwrap().as_mut() {
+            processor.record_clearance(&clearance.update(&costs, changes));
         }
+        drop(costs);
 
         drop(processor);
 
         let data = Arc::clone(&self.data);
-        thread::spawn(move || data.processor.lock().unwrap().update(&data.map, data.size.0, data.size.1));
+        thread::spawn(move || {
+            let mut processor = data.processor.lock().unwrap();
+            let costs = data.costs.read().unwrap();
+            processor.update(&costs, data.clearance.read().unwrap().as_ref(), &data.published)
+        });
+    }
+
+    /// costs returns copy of current costs, Grid is serializable with `serde` feature so this
+    /// is what save games and rollback snapshots store
+    #[inline]
+    pub fn costs(&self) -> Grid<i32> {
+        self.data.costs.read().unwrap().clone()
+    }
+
+    /// restore_costs replaces costs with snapshot of the same size, only tiles that differ
+    /// are passed to `update` so the field gets repaired instead of recomputed
+    pub fn restore_costs(&mut self, costs: &Grid<i32>) {
+        let current = self.data.costs.read().unwrap();
+        assert_eq!(current.size(), costs.size(), "snapshot has different size then the grid");
+        let changes: Vec<_> = costs.positions()
+            .filter(|&pos| current[pos] != costs[pos])
+            .map(|pos| (pos.0, pos.1, costs[pos]))
+            .collect();
+        drop(current);
+        self.update(&vec![], &changes);
+    }
+
+    /// field returns last complete field, it stays valid even when newer one gets published
+    #[inline]
+    pub fn field(&self) -> Arc<Field> {
+        self.data.published.current()
+    }
+
+    /// generation returns generation of last complete field, every update requests new one
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.data.published.generation()
+    }
+
+    /// is_ready returns whether field from last update is published
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.data.published.is_ready()
+    }
+
+    /// wait blocks until field from last update is published
+    #[inline]
+    pub fn wait(&self) {
+        self.data.published.wait();
     }
 
+    /// subscribe returns receiver that gets generation of every published field
+    #[inline]
+    pub fn subscribe(&self) -> Receiver<u64> {
+        self.data.published.subscribe()
+    }
+
+    #[inline]
+    pub fn get_step(&self, current: (usize, usize)) -> (usize, usize) {
+        self.field().get_step(current)
+    }
+}
+
+/// Field is complete distance field tagged with generation of update that produced it. Distance
+/// is sum of costs of entered tiles multiplied by STRAIGHT or DIAGONAL depending on the move.
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub generation: u64,
+    pub map: Grid<i32>,
+    /// diagonal is whether field was computed with diagonal moves
+    pub diagonal: bool,
+}
+
+impl Field {
+    /// get_step returns neighbour of current that lies on the shortest path to the goal, current
+    /// if there is none. Moves follow the same rules the field was computed with.
     pub fn get_step(&self, current: (usize, usize)) -> (usize, usize) {
+        let map = &self.map;
+        let here = map[current];
+        let directions: &[(i32, i32)] = if self.diagonal {&D8} else {&D4};
+
+        // predecessor on shortest path has the biggest drop of distance per unit of move
+        // length, unreachable tiles just head to lowest neighbour
         let mut best = current;
+        let mut score = (0i64, 1i64);
         let mut lowest = i32::MAX;
-        let map = self.data.map.lock().unwrap();
-        for d in D8.iter() {
-            let pos = (current.0 as i32 + d.0, current.1 as i32 + d.1);
-            if pos.0 < 0 || pos.1 < 0 || pos.0 == self.data.size.0 || pos.1 == self.data.size.1 {
-                continue
+        for d in directions.iter() {
+            let pos = match map.offset(current, *d) {
+                Some(pos) => pos,
+                None => continue,
+            };
+
+            let val = map[pos];
+            if val == INFINITY || !corner_free(current, pos, |x, y| map[(x, y)] != INFINITY) {
+                continue;
             }
 
-            let pos = (pos.0 as usize, pos.1 as usize);
+            if here == INFINITY {
+                if val < lowest {
+                    lowest = val;
+                    best = pos;
+                }
+                continue;
+            }
 
-            let val = map[pos.1][pos.0];
-            if val != INFINITY && (d.1 == 0 || d.0 == 0 || map[pos.1][current.0] != INFINITY ||
-                map[current.1][pos.0] != INFINITY) && val < lowest {
-                lowest = val;
+            let drop = (here - val) as i64;
+            let length = if d.0 == 0 || d.1 == 0 {STRAIGHT} else {DIAGONAL} as i64;
+            if drop * score.1 > score.0 * length {
+                score = (drop, length);
                 best = pos;
             }
         }
         best
     }
+
+    /// direction_at returns blended flow at position in tile units, tile (x, y) spans from
+    /// (x, y) to (x + 1, y + 1)
+    pub fn direction_at(&self, pos: Vect) -> Vect {
+        let mut direction = Vect::ZERO;
+        self.blend(pos, |x, y, weight| {
+            direction += self.flow(x, y) * weight;
+        });
+        if direction == Vect::ZERO {
+            return self.flow(pos.x.floor() as i32, pos.y.floor() as i32);
+        }
+        direction.norm()
+    }
+
+    /// distance_at returns bilinearly interpolated distance at position in tile units,
+    /// unreachable tiles are left out of the interpolation
+    pub fn distance_at(&self, pos: Vect) -> Option<f32> {
+        let mut distance = 0f32;
+        let mut total = 0f32;
+        self.blend(pos, |x, y, weight| {
+            distance += self.value(x, y).unwrap() as f32 * weight;
+            total += weight;
+        });
+        if total == 0f32 {
+            None
+        } else {
+            Some(distance / total)
+        }
+    }
+
+    /// blend calls f with each reachable tile out of four whose centers surround pos and its
+    /// bilinear weight
+    #[inline]
+    fn blend<F: FnMut(i32, i32, f32)>(&self, pos: Vect, mut f: F) {
+        let x = pos.x - 0.5;
+        let y = pos.y - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        for &(dx, dy, weight) in [
+            (0, 0, (1f32 - fx) * (1f32 - fy)),
+            (1, 0, fx * (1f32 - fy)),
+            (0, 1, (1f32 - fx) * fy),
+            (1, 1, fx * fy),
+        ].iter() {
+            if weight > 0f32 && self.value(x0 + dx, y0 + dy).is_some() {
+                f(x0 + dx, y0 + dy, weight);
+            }
+        }
+    }
+
+    /// flow returns direction of steepest descent of distance at tile center, it never points
+    /// towards missing neighbour so units are not pushed into walls. Where neighbours cancel
+    /// out it falls back to direction of `get_step`.
+    fn flow(&self, x: i32, y: i32) -> Vect {
+        let here = match self.value(x, y) {
+            Some(here) => here,
+            None => return Vect::ZERO,
+        };
+        let slope = |lower: Option<i32>, upper: Option<i32>| match (lower, upper) {
+            (Some(l), Some(u)) => (u - l) as f32 / 2f32,
+            (Some(l), None) => (here - l).max(0) as f32,
+            (None, Some(u)) => (u - here).min(0) as f32,
+            (None, None) => 0f32,
+        };
+
+        let gradient = Vect::new(
+            slope(self.value(x - 1, y), self.value(x + 1, y)),
+            slope(self.value(x, y - 1), self.value(x, y + 1)),
+        );
+        if gradient != Vect::ZERO && here != 0 {
+            return gradient.inverted().norm();
+        }
+
+        let current = (x as usize, y as usize);
+        let step = self.get_step(current);
+        Vect::new(step.0 as f32 - x as f32, step.1 as f32 - y as f32).norm()
+    }
+
+    /// value returns distance of tile, None if tile is outside or unreachable
+    #[inline]
+    fn value(&self, x: i32, y: i32) -> Option<i32> {
+        match self.map.get_signed(x, y) {
+            Some(&INFINITY) | None => None,
+            Some(&value) => Some(value),
+        }
+    }
+}
+
+/// corner_free returns whether move from current to pos does not cut corner of impassable tile,
+/// diagonal move is allowed only if both tiles it passes by are free
+#[inline]
+pub fn corner_free<F: Fn(usize, usize) -> bool>(current: (usize, usize), pos: (usize, usize), free: F) -> bool {
+    current.0 == pos.0 || current.1 == pos.1 || free(current.0, pos.1) && free(pos.0, current.1)
+}
+
+struct State {
+    requested: u64,
+    published: u64,
+    subscribers: Vec<Sender<u64>>,
+    /// closed is set when field is dropped and nothing will be published anymore
+    closed: bool,
 }
 
+/// Published is front buffer of double buffered field. Readers only clone the Arc, processor
+/// fills its back buffer without any lock and swaps it in when the field is complete.
+pub struct Published {
+    front: RwLock<Arc<Field>>,
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+impl Published {
+    pub fn new(field: Field) -> Self {
+        Self {
+            front: RwLock::new(Arc::new(field)),
+            state: Mutex::new(State { requested: 0, published: 0, subscribers: vec![], closed: false }),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// current returns last complete field
+    #[inline]
+    pub fn current(&self) -> Arc<Field> {
+        Arc::clone(&self.front.read().unwrap())
+    }
 
+    /// generation returns generation of last complete field
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().published
+    }
+
+    /// is_ready returns whether last requested field is published
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.published >= state.requested
+    }
+
+    /// wait blocks until last requested field is published, returns false if field was closed
+    /// before that
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let requested = state.requested;
+        while state.published < requested {
+            if state.closed {
+                return false;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+        true
+    }
+
+    /// close wakes everyone waiting, field will not be published anymore
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    /// subscribe returns receiver that gets generation of every published field
+    pub fn subscribe(&self) -> Receiver<u64> {
+        let (sender, receiver) = channel();
+        self.state.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    /// request returns generation for new update
+    #[inline]
+    pub(crate) fn request(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.requested += 1;
+        state.requested
+    }
+
+    /// publish swaps field to front and returns the old one
+    fn publish(&self, field: Arc<Field>) -> Arc<Field> {
+        let generation = field.generation;
+        let old = std::mem::replace(&mut *self.front.write().unwrap(), field);
+
+        let mut state = self.state.lock().unwrap();
+        state.published = state.published.max(generation);
+        state.subscribers.retain(|s| s.send(generation).is_ok());
+        self.ready.notify_all();
+
+        old
+    }
+}
 
 pub struct Data {
-    pub map: Mutex<Vec<Vec<i32>>>,
+    pub published: Published,
     pub processor: Mutex<Processor>,
+    pub costs: RwLock<Grid<i32>>,
+    pub clearance: RwLock<Option<Clearance>>,
     size: (i32, i32),
 }
 
 impl Data {
-    pub fn new(w: usize, h: usize, terminator: Receiver<()>, costs: &Vec<Vec<i32>>) -> Self {
-        let map = vec![vec![INFINITY; w]; h];
+    pub fn new(terminator: Receiver<()>, costs: &Grid<i32>, diagonal: bool) -> Self {
+        let (w, h) = costs.size();
         Self {
-            map: Mutex::new(map.clone()),
-            processor: Mutex::new(Processor {
-                terminator,
-                workspace: map,
-                frontier: Vec::with_capacity(w + h),
-                collector: Vec::with_capacity(w + h),
-                costs: costs.clone(),
-            }),
+            published: Published::new(Field { generation: 0, map: Grid::new(w, h, INFINITY), diagonal }),
+            processor: Mutex::new(Processor::new(w, h, terminator, diagonal)),
+            costs: RwLock::new(costs.clone()),
+            clearance: RwLock::new(None),
             size: (w as i32, h as i32),
         }
     }
 }
 
+/// Processor computes one field, it does not own the costs so many processors can share them
 pub struct Processor {
     terminator: Receiver<()>,
-    workspace: Vec<Vec<i32>>,
+    workspace: Grid<i32>,
     frontier: Vec<(usize, usize)>,
     collector: Vec<(usize, usize)>,
-    costs: Vec<Vec<i32>>,
+    goals: Vec<(usize, usize)>,
+    changes: Vec<(usize, usize, i32)>,
+    clearance_changes: Vec<(usize, usize, i32)>,
+    open: BinaryHeap<Reverse<(i32, (usize, usize))>>,
+    diagonal: bool,
+    required: i32,
+    valid: bool,
+    generation: u64,
+    spare: Option<Arc<Field>>,
 }
 
 impl Processor {
+    pub fn new(w: usize, h: usize, terminator: Receiver<()>, diagonal: bool) -> Self {
+        Self {
+            terminator,
+            workspace: Grid::new(w, h, INFINITY),
+            frontier: Vec::with_capacity(w + h),
+            collector: Vec::with_capacity(w + h),
+            goals: vec![],
+            changes: vec![],
+            clearance_changes: vec![],
+            open: BinaryHeap::new(),
+            diagonal,
+            required: 0,
+            valid: false,
+            generation: 0,
+            spare: None,
+        }
+    }
 
-    pub fn update(&mut self, map: &Mutex<Vec<Vec<i32>>>, w: i32, h: i32) {
+    /// set_frontier replaces goals for next update and assigns generation it will publish
+    #[inline]
+    pub(crate) fn set_frontier(&mut self, frontier: &[(usize, usize)], generation: u64) {
+        self.frontier.clear();
+        self.frontier.extend(frontier);
+        self.generation = generation;
+    }
 
-        for row in self.workspace.iter_mut() {
-            for tile in row.iter_mut() {
-                *tile = INFINITY;
-            }
+    /// set_generation assigns generation next update will publish and keeps the frontier
+    #[inline]
+    pub(crate) fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    /// set_clearance sets clearance units using the field need, changing it invalidates the field
+    #[inline]
+    pub(crate) fn set_clearance(&mut self, required: i32) {
+        if self.required != required {
+            self.required = required;
+            self.valid = false;
         }
+    }
 
-        for pos in self.frontier.iter() {
-            self.workspace[pos.1][pos.0] = 0;
+    /// apply writes changes to costs and remembers them so next update can repair the field
+    pub fn apply(&mut self, costs: &mut Grid<i32>, changes: &[(usize, usize, i32)]) {
+        for change in changes.iter() {
+            self.changes.push((change.0, change.1, costs[(change.0, change.1)]));
+            costs[(change.0, change.1)] = change.2;
         }
+    }
 
-        let mut current_cost;
-        let mut pos;
-        let mut con;
-        let mut value;
-        let mut previous;
-        let mut supposed;
+    /// record remembers changes that were already written to shared costs
+    #[inline]
+    pub(crate) fn record(&mut self, changes: &[(usize, usize, i32)]) {
+        self.changes.extend(changes);
+    }
 
-        while self.frontier.len() != 0 {
-            match self.terminator.try_recv() {
-                Ok(_) => {
-                    self.frontier.clear();
-                    return
-                },
-                Err(err) => match err {
-                    TryRecvError::Disconnected => {
-                        self.frontier.clear();
-                        return
-                    },
-                    _ => {}
-                }
+    /// record_clearance remembers old clearance of tiles where it changed
+    #[inline]
+    pub(crate) fn record_clearance(&mut self, changes: &[(usize, usize, i32)]) {
+        if self.required > 1 {
+            self.clearance_changes.extend(changes);
+        }
+    }
+
+    /// update recomputes the field. If frontier is empty or same as in previous update, goals
+    /// are kept and only the region affected by cost changes is invalidated and repaired.
+    /// Otherwise whole field is computed from scratch. Clearance is needed only if field was
+    /// given required clearance.
+    pub fn update(&mut self, costs: &Grid<i32>, clearance: Option<&Clearance>, published: &Published) {
+        while self.terminator.try_recv().is_ok() {}
+
+        let terrain = Terrain { costs, clearance: clearance.filter(|_| self.required > 1), required: self.required };
+        if self.valid && (self.frontier.is_empty() || self.same_goals()) {
+            self.frontier.clear();
+            self.repair(terrain);
+        } else {
+            self.changes.clear();
+            self.clearance_changes.clear();
+            if self.frontier.is_empty() {
+                self.frontier.extend(&self.goals);
+            } else {
+                self.goals.clear();
+                self.goals.extend(&self.frontier);
             }
 
+            self.workspace.fill(INFINITY);
+
+            for pos in self.frontier.iter() {
+                self.workspace[*pos] = 0;
+            }
+        }
 
+        self.valid = self.propagate(terrain);
+        if !self.valid {
+            return;
+        }
 
-            for current in self.frontier.drain(..) {
-                current_cost = self.workspace[current.1][current.0];
-                for d in D4.iter() {
-                    pos = (current.0 as i32 + d.0, current.1 as i32 + d.1);
+        let mut back = match self.spare.take() {
+            Some(spare) if Arc::strong_count(&spare) == 1 => spare,
+            _ => Arc::new(Field { generation: 0, map: Grid::default(), diagonal: self.diagonal }),
+        };
+        let field = Arc::get_mut(&mut back).unwrap();
+        field.map.clone_from(&self.workspace);
+        field.generation = self.generation;
+        self.spare = Some(published.publish(back));
+    }
 
-                    if  pos.0 < 0 || pos.1 < 0 || pos.0 == w || pos.1 == h {
-                        continue
-                    }
+    #[inline]
+    fn same_goals(&self) -> bool {
+        self.frontier.len() == self.goals.len() && self.frontier.iter().all(|g| self.goals.contains(g))
+    }
 
-                    con = (pos.0 as usize, pos.1 as usize);
+    /// repair invalidates tiles whose distance depended on tiles that got more expensive or on
+    /// diagonal moves that got blocked, then pushes border of invalidated region together with
+    /// surroundings of tiles that got cheaper to frontier
+    fn repair(&mut self, terrain: Terrain) {
+        let old_clearance: HashMap<(usize, usize), i32, FastHash> = self.clearance_changes.iter()
+            .rev()
+            .map(|c| ((c.0, c.1), c.2))
+            .collect();
+        let mut old_costs: HashMap<(usize, usize), i32, FastHash> = self.changes.iter()
+            .rev()
+            .map(|c| ((c.0, c.1), c.2))
+            .collect();
+        for &pos in old_clearance.keys() {
+            old_costs.entry(pos).or_insert(terrain.costs[pos]);
+        }
+        for (pos, cost) in old_costs.iter_mut() {
+            let clearance = old_clearance.get(pos).cloned().or_else(|| terrain.clearance.map(|c| c.get(*pos)));
+            *cost = terrain.effective(*cost, clearance);
+        }
+        self.clearance_changes.clear();
+        let old = |pos: (usize, usize)| old_costs.get(&pos).cloned().unwrap_or_else(|| terrain.cost(pos));
+        let directions: &[(i32, i32)] = if self.diagonal {&D8} else {&D4};
+        let mut invalid = vec![];
+        let mut stack = vec![];
+
+        for (&(x, y), &previous) in old_costs.iter() {
+            let new = terrain.cost((x, y));
+            if new == previous || self.goals.contains(&(x, y)) {
+                continue;
+            }
+
+            if new == -1 || previous != -1 && new > previous {
+                if self.workspace[(x, y)] != INFINITY {
+                    stack.push(((x, y), self.workspace[(x, y)]));
+                    self.workspace[(x, y)] = INFINITY;
+                    invalid.push((x, y));
+                }
+            } else {
+                self.collector.push((x, y));
+            }
 
-                    value = self.costs[con.1][con.0];
-                    if value == -1 {
-                        continue
+            // blocked tile also blocks diagonal moves around its corners
+            if self.diagonal && new == -1 && previous != -1 {
+                for d in D8[4..].iter() {
+                    let (a, b) = match (self.workspace.offset((x, y), (d.0, 0)), self.workspace.offset((x, y), (0, d.1))) {
+                        (Some(a), Some(b)) => (a, b),
+                        _ => continue,
+                    };
+                    for &(from, to) in [(a, b), (b, a)].iter() {
+                        let (value, target) = (self.workspace[from], self.workspace[to]);
+                        if value != INFINITY && target != INFINITY && old(to) != -1 &&
+                            target == value + old(to) * DIAGONAL && !self.goals.contains(&to) {
+                            stack.push((to, target));
+                            self.workspace[to] = INFINITY;
+                            invalid.push(to);
+                        }
                     }
+                }
+            }
+        }
+        self.changes.clear();
 
-                    previous = self.workspace[con.1][con.0];
-                    supposed = current_cost + value;
-                    if previous == -1 || previous > supposed {
-                        self.workspace[con.1][con.0] = supposed;
-                        self.collector.push(con);
+        while let Some((current, value)) = stack.pop() {
+            for d in directions.iter() {
+                let pos = match self.workspace.offset(current, *d) {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                let cost = old(pos);
+                let previous = self.workspace[pos];
+                if previous == INFINITY || cost == -1 ||
+                    !corner_free(current, pos, |x, y| old((x, y)) != -1) {
+                    continue;
+                }
+                let step = if d.0 == 0 || d.1 == 0 {STRAIGHT} else {DIAGONAL};
+                if previous != value + cost * step || self.goals.contains(&pos) {
+                    continue;
+                }
+                stack.push((pos, previous));
+                self.workspace[pos] = INFINITY;
+                invalid.push(pos);
+            }
+        }
+
+        invalid.append(&mut self.collector);
+        for current in invalid.into_iter() {
+            for d in directions.iter() {
+                if let Some(pos) = self.workspace.offset(current, *d) {
+                    if self.workspace[pos] != INFINITY {
+                        self.frontier.push(pos);
                     }
                 }
             }
-            self.frontier.append(&mut self.collector);
         }
+    }
+
+    /// propagate runs Dijkstra from frontier until there is nothing to improve, returns false
+    /// if it was terminated
+    fn propagate(&mut self, terrain: Terrain) -> bool {
+        let Self { terminator, workspace, frontier, open, diagonal, .. } = self;
+
+        open.clear();
+        for pos in frontier.drain(..) {
+            open.push(Reverse((workspace[pos], pos)));
+        }
+
+        let mut expanded = 0;
+        propagate(workspace, open, *diagonal, i32::MAX, |pos| terrain.cost(pos), |_, _| {
+            expanded += 1;
+            expanded % POLL != 0 || matches!(terminator.try_recv(), Err(TryRecvError::Empty))
+        })
+    }
+}
+
+/// propagate runs Dijkstra over workspace from tiles in open until there is nothing to
+/// improve, tiles further then limit are left untouched. Cost -1 is impassable and diagonal
+/// moves cannot cut corners. Visit is called with every tile once its distance is final,
+/// returning false from it terminates the search and makes propagate return false.
+pub(crate) fn propagate<C, V>(
+    workspace: &mut Grid<i32>,
+    open: &mut BinaryHeap<Reverse<(i32, (usize, usize))>>,
+    diagonal: bool,
+    limit: i32,
+    cost: C,
+    mut visit: V,
+) -> bool
+where
+    C: Fn((usize, usize)) -> i32,
+    V: FnMut((usize, usize), i32) -> bool,
+{
+    let directions: &[(i32, i32)] = if diagonal {&D8} else {&D4};
+    while let Some(Reverse((value, current))) = open.pop() {
+        if value > workspace[current] {
+            continue;
+        }
+
+        if !visit(current, value) {
+            open.clear();
+            return false;
+        }
+
+        for d in directions.iter() {
+            let con = match workspace.offset(current, *d) {
+                Some(pos) => pos,
+                None => continue,
+            };
+
+            let tile = cost(con);
+            if tile == -1 || !corner_free(current, con, |x, y| cost((x, y)) != -1) {
+                continue
+            }
+
+            let previous = workspace[con];
+            let supposed = value + tile * if d.0 == 0 || d.1 == 0 {STRAIGHT} else {DIAGONAL};
+            if supposed <= limit && (previous == INFINITY || previous > supposed) {
+                workspace[con] = supposed;
+                open.push(Reverse((supposed, con)));
+            }
+        }
+    }
+
+    true
+}
+
+/// Terrain is view of costs where tiles without required clearance are impassable
+#[derive(Copy, Clone)]
+struct Terrain<'a> {
+    costs: &'a Grid<i32>,
+    clearance: Option<&'a Clearance>,
+    required: i32,
+}
 
-        map.lock().unwrap().clone_from(&self.workspace);
+impl Terrain<'_> {
+    #[inline]
+    fn cost(&self, pos: (usize, usize)) -> i32 {
+        self.effective(self.costs[pos], self.clearance.map(|c| c.get(pos)))
+    }
+
+    #[inline]
+    fn effective(&self, cost: i32, clearance: Option<i32>) -> i32 {
+        if clearance.is_some_and(|c| c < self.required) {-1} else {cost}
     }
 }
 
+
 #[cfg(test)]
 mod tests {
-    use crate::entity::pathfinder::{Processor, Data};
+    use crate::entity::pathfinder::{Data, PathFinder, INFINITY};
+    use crate::entity::astar::{Search, STRAIGHT, DIAGONAL};
+    use crate::entity::clearance::Clearance;
+    use crate::entity::grid::Grid;
+    use crate::math::vect::Vect;
     use std::sync::mpsc::channel;
+    use rand::Rng;
 
     #[test]
     fn update_test() {
-        let costs = vec![vec![1; 10]; 10];
+        let costs = Grid::new(10, 10, 1);
         let chan = channel();
-        let pro = Data::new(10, 10, chan.1, &costs);
+        let pro = Data::new(chan.1, &costs, false);
         {
             pro.processor.lock().unwrap().frontier.push((0, 0));
         }
-        pro.processor.lock().unwrap().update(&pro.map, 10, 10);
-        for i in pro.map.lock().unwrap().iter() {
+        pro.processor.lock().unwrap().update(&costs, None, &pro.published);
+        for i in pro.published.current().map.rows() {
             println!("{:?}", i);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn repair_test() {
+        let mut rng = rand::thread_rng();
+        for &(diagonal, required) in [(false, 0), (true, 0), (true, 2)].iter() {
+            let mut costs = Grid::from_fn(30, 30, |_| rng.gen_range(1, 5));
+            let mut clearance = Clearance::new(&costs, 3);
+            let chan = channel();
+            let pro = Data::new(chan.1, &costs, diagonal);
+            {
+                let mut processor = pro.processor.lock().unwrap();
+                processor.set_clearance(required);
+                processor.frontier.push((3, 4));
+                processor.update(&costs, Some(&clearance), &pro.published);
+            }
+
+            for _ in 0..20 {
+                let mut processor = pro.processor.lock().unwrap();
+                let changes: Vec<_> = (0..10)
+                    .map(|_| (rng.gen_range(0, 30), rng.gen_range(0, 30), rng.gen_range(-1, 5)))
+                    .collect();
+                processor.apply(&mut costs, &changes);
+                processor.record_clearance(&clearance.update(&costs, &changes));
+                processor.update(&costs, Some(&clearance), &pro.published);
+
+                let chan = channel();
+                let fresh = Data::new(chan.1, &costs, diagonal);
+                let mut fresh_processor = fresh.processor.lock().unwrap();
+                fresh_processor.set_clearance(required);
+                fresh_processor.frontier.push((3, 4));
+                fresh_processor.update(&costs, Some(&clearance), &fresh.published);
+                assert_eq!(fresh.published.current().map, pro.published.current().map);
+            }
+        }
+    }
+
+    #[test]
+    fn dijkstra_test() {
+        let mut rng = rand::thread_rng();
+        let mut costs = Grid::from_fn(20, 20, |_| if rng.gen_range(0, 6) == 0 {-1} else {rng.gen_range(1, 6)});
+        costs[(0, 0)] = 1;
+
+        let chan = channel();
+        let pro = Data::new(chan.1, &costs, true);
+        pro.processor.lock().unwrap().frontier.push((0, 0));
+        pro.processor.lock().unwrap().update(&costs, None, &pro.published);
+        let field = pro.published.current();
+
+        let search = Search::new(true);
+        for y in 0..20 {
+            for x in 0..20 {
+                let expected = search.find_in((0, 0), (x, y), &costs, (0, 0, 20, 20), 0).map(|r| r.0);
+                let value = field.map[(x, y)];
+                assert_eq!(expected, if value == INFINITY {None} else {Some(value)});
+
+                // walking the field costs exactly its value
+                let mut current = (x, y);
+                let mut walked = 0;
+                while value != INFINITY && current != (0, 0) {
+                    let next = field.get_step(current);
+                    walked += costs[current] * if next.0 == current.0 || next.1 == current.1 {STRAIGHT} else {DIAGONAL};
+                    current = next;
+                }
+                if value != INFINITY {
+                    assert_eq!(value, walked);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn direction_test() {
+        let mut costs = Grid::new(20, 20, 1);
+        for y in 0..15 {
+            costs[(5, y)] = -1;
+        }
+        let mut finder = PathFinder::with_diagonal(&costs, true)
+            .with_space(Vect::new(100f32, 100f32), Vect::new(10f32, 10f32));
+        finder.update(&vec![(0, 0)], &vec![]);
+        finder.wait();
+
+        let goal = finder.tile_center((0, 0));
+        assert_eq!((0, 0), finder.tile_at(goal));
+        assert_eq!(Vect::ZERO, finder.direction_at(goal));
+        assert_eq!(Some(0f32), finder.distance_at(goal));
+
+        let pos = finder.tile_center((3, 2)) + Vect::new(2f32, 3f32);
+        assert!(finder.direction_at(pos).dot((goal - pos).norm()) > 0.9);
+        let value = finder.field().map[(3, 2)] as f32;
+        assert!(finder.distance_at(pos).unwrap() > value);
+
+        let pos = finder.tile_center((6, 5));
+        let direction = finder.direction_at(pos);
+        assert!(direction.y > 0.7 && direction.x > -0.3);
+
+        let path = finder.path_from(finder.tile_center((9, 2)));
+        assert_eq!(4, path.len());
+        assert_eq!(Some(&goal), path.last());
+        assert_eq!(finder.tile_center((6, 15)), path[1]);
+
+        let snapshot = finder.costs();
+        finder.update(&vec![], &vec![(5, 15, -1), (5, 16, -1)]);
+        finder.wait();
+        assert_ne!(path, finder.path_from(finder.tile_center((9, 2))));
+        finder.restore_costs(&snapshot);
+        finder.wait();
+        assert_eq!(snapshot, finder.costs());
+        assert_eq!(path, finder.path_from(finder.tile_center((9, 2))));
+    }
+
+    #[test]
+    fn wait_test() {
+        let costs = Grid::new(100, 100, 1);
+        let mut finder = PathFinder::new(&costs);
+        let events = finder.subscribe();
+        let old = finder.field();
+
+        finder.update(&vec![(0, 0)], &vec![]);
+        finder.update(&vec![(0, 0)], &vec![(1, 0, 3)]);
+        finder.wait();
+
+        assert!(finder.is_ready());
+        assert_eq!(2, finder.generation());
+        assert_eq!(2, finder.field().generation);
+        assert_eq!(0, old.generation);
+        assert_eq!(-1, old.map[(1, 0)]);
+        assert_eq!(30, finder.field().map[(1, 0)]);
+        assert_eq!((0, 1), finder.get_step((1, 1)));
+        assert_eq!(2, events.iter().find(|g| *g == 2).unwrap());
+    }
+}
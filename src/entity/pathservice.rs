@@ -0,0 +1,362 @@
+use crate::entity::pathfinder::{Processor, Published, Field, INFINITY};
+use crate::entity::clearance::Clearance;
+use crate::entity::grid::Grid;
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex, RwLock, Condvar, MutexGuard};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::mpsc::{Sender, Receiver, channel};
+use std::thread::{self, JoinHandle};
+
+struct Slot {
+    published: Published,
+    processor: Mutex<Processor>,
+    terminator: Mutex<Sender<()>>,
+    priority: AtomicI32,
+    last_used: AtomicU64,
+}
+
+impl Slot {
+    /// lock locks processor, running computation is terminated first
+    fn lock(&self) -> MutexGuard<'_, Processor> {
+        match self.processor.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                self.terminator.lock().unwrap().send(()).unwrap();
+                self.processor.lock().unwrap()
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+struct Job {
+    priority: i32,
+    seq: u64,
+    name: String,
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct Queue {
+    jobs: BinaryHeap<Job>,
+    pending: HashMap<String, (u64, i32)>,
+    seq: u64,
+    shutdown: bool,
+}
+
+struct Shared {
+    costs: RwLock<Grid<i32>>,
+    clearance: RwLock<Option<Clearance>>,
+    size: (usize, usize),
+    fields: Mutex<HashMap<String, Arc<Slot>>>,
+    queue: Mutex<Queue>,
+    signal: Condvar,
+    clock: AtomicU64,
+}
+
+impl Shared {
+    #[inline]
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, AtomicOrdering::Relaxed)
+    }
+
+    /// schedule queues field computation, if field is already waiting only its priority
+    /// can be raised so any number of requests between two computations costs just one
+    fn schedule(&self, name: &str, priority: i32) {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(&(_, p)) = queue.pending.get(name) {
+            if p >= priority {
+                return;
+            }
+        }
+        queue.seq += 1;
+        let seq = queue.seq;
+        queue.pending.insert(name.to_string(), (seq, priority));
+        queue.jobs.push(Job { priority, seq, name: name.to_string() });
+        drop(queue);
+        self.signal.notify_one();
+    }
+
+    fn work(&self) {
+        loop {
+            let name = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    if queue.shutdown {
+                        return;
+                    }
+                    match queue.jobs.pop() {
+                        Some(job) => {
+                            if queue.pending.get(&job.name).map(|p| p.0) == Some(job.seq) {
+                                queue.pending.remove(&job.name);
+                                break job.name;
+                            }
+                        },
+                        None => queue = self.signal.wait(queue).unwrap(),
+                    }
+                }
+            };
+
+            let slot = match self.fields.lock().unwrap().get(&name) {
+                Some(slot) => Arc::clone(slot),
+                None => continue,
+            };
+
+            let mut processor = slot.processor.lock().unwrap();
+            let costs = self.costs.read().unwrap();
+            processor.update(&costs, self.clearance.read().unwrap().as_ref(), &slot.published);
+        }
+    }
+}
+
+/// PathService manages many named flow fields over one shared cost grid and computes them on
+/// fixed pool of worker threads. Requests are prioritized and coalesced, requesting a field
+/// that is being computed terminates the computation so workers never waste time on
+/// superseded goals. Only max_fields fields are kept, least recently used one is dropped when
+/// new one is needed.
+pub struct PathService {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+    max_fields: usize,
+    diagonal: bool,
+}
+
+impl PathService {
+    /// new creates service with given count of worker threads, diagonal is passed to every
+    /// field, see `PathFinder::with_diagonal`
+    pub fn new(costs: &Grid<i32>, workers: usize, max_fields: usize, diagonal: bool) -> Self {
+        let shared = Arc::new(Shared {
+            costs: RwLock::new(costs.clone()),
+            clearance: RwLock::new(None),
+            size: costs.size(),
+            fields: Mutex::new(HashMap::new()),
+            queue: Mutex::new(Queue { jobs: BinaryHeap::new(), pending: HashMap::new(), seq: 0, shutdown: false }),
+            signal: Condvar::new(),
+            clock: AtomicU64::new(0),
+        });
+
+        let workers = (0..workers.max(1)).map(|_| {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.work())
+        }).collect();
+
+        Self { shared, workers, max_fields: max_fields.max(1), diagonal }
+    }
+
+    /// with_clearance makes service maintain clearance map with given cap so fields can be
+    /// requested for bigger units, see `request_sized`
+    pub fn with_clearance(self, cap: i32) -> Self {
+        let clearance = Clearance::new(&self.shared.costs.read().unwrap(), cap);
+        *self.shared.clearance.write().unwrap() = Some(clearance);
+        self
+    }
+
+    /// request sets goals of named field and schedules its computation, field is created if
+    /// it does not exist, higher priority is computed sooner
+    #[inline]
+    pub fn request(&self, name: &str, frontier: &[(usize, usize)], priority: i32) {
+        self.request_sized(name, frontier, priority, 0)
+    }
+
+    /// request_sized is request for units that need given clearance, see `Clearance::required`,
+    /// it has effect only if service was created `with_clearance`
+    pub fn request_sized(&self, name: &str, frontier: &[(usize, usize)], priority: i32, required: i32) {
+        let slot = self.slot(name);
+        slot.priority.store(priority, AtomicOrdering::Relaxed);
+        let generation = slot.published.request();
+        let mut processor = slot.lock();
+        processor.set_clearance(required);
+        processor.set_frontier(frontier, generation);
+        drop(processor);
+        self.shared.schedule(name, priority);
+    }
+
+    /// update_costs applies changes to shared cost grid and schedules repair of all fields
+    pub fn update_costs(&self, changes: &[(usize, usize, i32)]) {
+        let slots: Vec<(String, Arc<Slot>)> = self.shared.fields.lock().unwrap()
+            .iter()
+            .map(|(name, slot)| (name.clone(), Arc::clone(slot)))
+            .collect();
+
+        let mut processors: Vec<MutexGuard<'_, Processor>> = slots.iter().map(|s| s.1.lock()).collect();
+        let mut costs = self.shared.costs.write().unwrap();
+        for change in changes.iter() {
+            let old = costs[(change.0, change.1)];
+            costs[(change.0, change.1)] = change.2;
+            for processor in processors.iter_mut() {
+                processor.record(&[(change.0, change.1, old)]);
+            }
+        }
+        if let Some(clearance) = self.shared.clearance.write().unwrap().as_mut() {
+            let changed = clearance.update(&costs, changes);
+            for processor in processors.iter_mut() {
+                processor.record_clearance(&changed);
+            }
+        }
+        drop(costs);
+
+        for ((name, slot), mut processor) in slots.iter().zip(processors) {
+            let generation = slot.published.request();
+            processor.set_generation(generation);
+            drop(processor);
+            self.shared.schedule(name, slot.priority.load(AtomicOrdering::Relaxed));
+        }
+    }
+
+    /// field returns last complete field of given name
+    pub fn field(&self, name: &str) -> Option<Arc<Field>> {
+        let fields = self.shared.fields.lock().unwrap();
+        let slot = fields.get(name)?;
+        slot.last_used.store(self.shared.tick(), AtomicOrdering::Relaxed);
+        Some(slot.published.current())
+    }
+
+    /// get_step returns next step towards goals of named field
+    #[inline]
+    pub fn get_step(&self, name: &str, current: (usize, usize)) -> Option<(usize, usize)> {
+        Some(self.field(name)?.get_step(current))
+    }
+
+    /// is_ready returns whether last request of named field is computed
+    pub fn is_ready(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|slot| slot.published.is_ready())
+    }
+
+    /// wait blocks until last request of named field is computed, returns false if there is
+    /// no such field or it was removed or evicted before it got computed
+    pub fn wait(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|slot| slot.published.wait())
+    }
+
+    /// subscribe returns receiver that gets generation of every published field of given name
+    pub fn subscribe(&self, name: &str) -> Option<Receiver<u64>> {
+        Some(self.get(name)?.published.subscribe())
+    }
+
+    /// remove drops the field, running computation is terminated
+    pub fn remove(&self, name: &str) -> bool {
+        let slot = self.shared.fields.lock().unwrap().remove(name);
+        self.shared.queue.lock().unwrap().pending.remove(name);
+        match slot {
+            Some(slot) => {
+                slot.published.close();
+                let _ = slot.terminator.lock().unwrap().send(());
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// field_count returns count of fields kept in memory
+    #[inline]
+    pub fn field_count(&self) -> usize {
+        self.shared.fields.lock().unwrap().len()
+    }
+
+    #[inline]
+    fn get(&self, name: &str) -> Option<Arc<Slot>> {
+        self.shared.fields.lock().unwrap().get(name).cloned()
+    }
+
+    fn slot(&self, name: &str) -> Arc<Slot> {
+        let mut fields = self.shared.fields.lock().unwrap();
+        let tick = self.shared.tick();
+        if let Some(slot) = fields.get(name) {
+            slot.last_used.store(tick, AtomicOrdering::Relaxed);
+            return Arc::clone(slot);
+        }
+
+        if fields.len() >= self.max_fields {
+            let oldest = fields.iter()
+                .min_by_key(|(_, slot)| slot.last_used.load(AtomicOrdering::Relaxed))
+                .map(|(name, _)| name.clone())
+                .unwrap();
+            let slot = fields.remove(&oldest).unwrap();
+            slot.published.close();
+            let _ = slot.terminator.lock().unwrap().send(());
+            self.shared.queue.lock().unwrap().pending.remove(&oldest);
+        }
+
+        let (w, h) = self.shared.size;
+        let (terminator, receiver) = channel();
+        let slot = Arc::new(Slot {
+            published: Published::new(Field { generation: 0, map: Grid::new(w, h, INFINITY), diagonal: self.diagonal }),
+            processor: Mutex::new(Processor::new(w, h, receiver, self.diagonal)),
+            terminator: Mutex::new(terminator),
+            priority: AtomicI32::new(0),
+            last_used: AtomicU64::new(tick),
+        });
+        fields.insert(name.to_string(), Arc::clone(&slot));
+        slot
+    }
+}
+
+impl Drop for PathService {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        for slot in self.shared.fields.lock().unwrap().values() {
+            slot.published.close();
+            let _ = slot.terminator.lock().unwrap().send(());
+        }
+        self.shared.signal.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::pathservice::PathService;
+    use crate::entity::grid::Grid;
+
+    #[test]
+    fn request_test() {
+        let costs = Grid::new(50, 50, 1);
+        let service = PathService::new(&costs, 2, 2, true);
+
+        service.request("red", &[(0, 0)], 0);
+        service.request("blue", &[(49, 49)], 1);
+        service.wait("red");
+        service.wait("blue");
+
+        assert_eq!(Some((0, 0)), service.get_step("red", (1, 1)));
+        assert_eq!(Some((49, 49)), service.get_step("blue", (48, 48)));
+
+        service.update_costs(&[(0, 1, -1), (1, 0, -1)]);
+        service.wait("red");
+        assert_eq!(-1, service.field("red").unwrap().map[(2, 2)]);
+        assert_eq!(Some((48, 48)), service.get_step("blue", (47, 47)));
+
+        service.request_sized("green", &[(25, 25)], 0, 2);
+        assert_eq!(2, service.field_count());
+        assert!(service.field("red").is_none());
+        assert!(service.remove("green"));
+    }
+
+    #[test]
+    fn evict_test() {
+        let costs = Grid::new(300, 300, 1);
+        let service = PathService::new(&costs, 1, 1, true);
+        service.request("red", &[(0, 0)], 0);
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| service.wait("red"));
+            service.request("blue", &[(299, 299)], 0);
+            // waiter is woken up even if red got evicted before it was computed
+            waiter.join().unwrap();
+        });
+        assert!(!service.wait("red"));
+        assert!(service.wait("blue"));
+    }
+}
@@ -0,0 +1,565 @@
+use crate::math::rect::Rect;
+use crate::math::vect::Vect;
+use crate::math::clamp_f;
+use crate::math::sweep::closest_on_segment;
+use crate::entity::scanner::Scanner;
+use crate::entity::events::Events;
+use crate::entity::{FastHash, gen_hash};
+use std::collections::HashSet;
+use std::mem;
+
+/// Shape of a body, all shapes are in local space of the body. Bodies do not rotate.
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Circle(f32),
+    /// Aabb holds half width and half height
+    Aabb(Vect),
+    /// Polygon has to be convex
+    Polygon(Vec<Vect>),
+}
+
+impl Shape {
+    /// bounds returns bounding box of shape placed at pos
+    pub fn bounds(&self, pos: Vect) -> Rect {
+        match self {
+            Shape::Circle(r) => Rect::cube(pos, *r),
+            Shape::Aabb(half) => Rect::centered(pos, half.x * 2f32, half.y * 2f32),
+            Shape::Polygon(verts) => Rect::bounds_for(verts).moved(pos),
+        }
+    }
+
+    #[inline]
+    fn verts(&self, pos: Vect, buffer: &mut Vec<Vect>) {
+        buffer.clear();
+        match self {
+            Shape::Aabb(half) => buffer.extend(Rect::centered(pos, half.x * 2f32, half.y * 2f32).verts().iter()),
+            Shape::Polygon(verts) => buffer.extend(verts.iter().map(|v| *v + pos)),
+            Shape::Circle(_) => {},
+        }
+    }
+}
+
+/// Body is simulated object. Static body has zero mass and does not move, sensor detects
+/// contacts but does not collide.
+#[derive(Clone, Debug)]
+pub struct Body {
+    pub pos: Vect,
+    pub vel: Vect,
+    pub force: Vect,
+    pub shape: Shape,
+    pub restitution: f32,
+    pub friction: f32,
+    pub sensor: bool,
+    mass: f32,
+    inv_mass: f32,
+    scanned: Vect,
+}
+
+impl Body {
+    /// new creates body, mass of 0 makes body static
+    pub fn new(pos: Vect, shape: Shape, mass: f32) -> Self {
+        let mut body = Self {
+            pos,
+            vel: Vect::ZERO,
+            force: Vect::ZERO,
+            shape,
+            restitution: 0.2,
+            friction: 0.4,
+            sensor: false,
+            mass: 0f32,
+            inv_mass: 0f32,
+            scanned: pos,
+        };
+        body.set_mass(mass);
+        body
+    }
+
+    /// new_static creates body that never moves
+    #[inline]
+    pub fn new_static(pos: Vect, shape: Shape) -> Self {
+        Self::new(pos, shape, 0f32)
+    }
+
+    /// new_sensor creates static body that only reports contacts
+    #[inline]
+    pub fn new_sensor(pos: Vect, shape: Shape) -> Self {
+        let mut body = Self::new(pos, shape, 0f32);
+        body.sensor = true;
+        body
+    }
+
+    #[inline]
+    pub fn set_mass(&mut self, mass: f32) {
+        self.mass = mass;
+        self.inv_mass = if mass == 0f32 {0f32} else {1f32 / mass};
+    }
+
+    #[inline]
+    pub fn mass(&self) -> f32 {
+        self.mass
+    }
+
+    #[inline]
+    pub fn is_static(&self) -> bool {
+        self.inv_mass == 0f32
+    }
+
+    #[inline]
+    pub fn apply_impulse(&mut self, impulse: Vect) {
+        self.vel += impulse * self.inv_mass;
+    }
+
+    #[inline]
+    pub fn bounds(&self) -> Rect {
+        self.shape.bounds(self.pos)
+    }
+}
+
+/// Contact is overlap of two bodies found by narrowphase, normal points from a to b
+#[derive(Copy, Clone, Debug)]
+pub struct Contact {
+    pub a: usize,
+    pub b: usize,
+    pub normal: Vect,
+    pub depth: f32,
+}
+
+/// ContactEvent reports pair of bodies that started or stopped touching, lower id goes first
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContactEvent {
+    Begin(usize, usize),
+    End(usize, usize),
+}
+
+/// World simulates bodies with fixed time step. Broadphase is done with Scanner so bodies
+/// can be of any size but world is fastest when they are smaller then tile.
+pub struct World {
+    pub gravity: Vect,
+    time_step: f32,
+    pub iterations: usize,
+    /// fraction of penetration removed every step
+    pub correction: f32,
+    /// penetration that is tolerated to prevent jitter
+    pub slop: f32,
+    bodies: Vec<Option<Body>>,
+    free: Vec<usize>,
+    scanner: Scanner<usize>,
+    tile_size: Vect,
+    max_extent: f32,
+    accumulator: f32,
+    contacts: Vec<Contact>,
+    touching: HashSet<(usize, usize), FastHash>,
+    previous: HashSet<(usize, usize), FastHash>,
+    events: Events<ContactEvent>,
+    collector: Vec<usize>,
+    verts: (Vec<Vect>, Vec<Vect>),
+}
+
+impl World {
+    /// new creates world with w x h broadphase grid of given tile size
+    pub fn new(w: usize, h: usize, tile_size: Vect) -> Self {
+        Self {
+            gravity: Vect::ZERO,
+            time_step: 1f32 / 60f32,
+            iterations: 4,
+            correction: 0.8,
+            slop: 0.01,
+            bodies: vec![],
+            free: vec![],
+            scanner: Scanner::new(w, h, tile_size),
+            tile_size,
+            max_extent: 0f32,
+            accumulator: 0f32,
+            contacts: vec![],
+            touching: HashSet::with_hasher(gen_hash()),
+            previous: HashSet::with_hasher(gen_hash()),
+            events: Events::new(),
+            collector: vec![],
+            verts: (vec![], vec![]),
+        }
+    }
+
+    /// with_time_step sets length of one fixed step
+    #[inline]
+    pub fn with_time_step(mut self, time_step: f32) -> Self {
+        self.set_time_step(time_step);
+        self
+    }
+
+    /// set_time_step sets length of one fixed step, it panics if it is not positive as `step`
+    /// would never finish
+    #[inline]
+    pub fn set_time_step(&mut self, time_step: f32) {
+        assert!(time_step > 0f32, "time step has to be positive, got {}", time_step);
+        self.time_step = time_step;
+    }
+
+    #[inline]
+    pub fn time_step(&self) -> f32 {
+        self.time_step
+    }
+
+    /// add adds body to world and returns its id
+    pub fn add(&mut self, mut body: Body) -> usize {
+        let b = body.bounds();
+        self.max_extent = self.max_extent.max(b.width().max(b.height()) / 2f32);
+        body.scanned = body.pos;
+
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.bodies[id] = Some(body);
+                id
+            },
+            None => {
+                self.bodies.push(Some(body));
+                self.bodies.len() - 1
+            }
+        };
+        self.scanner.insert(self.bodies[id].as_ref().unwrap().pos, id);
+
+        id
+    }
+
+    /// remove removes body from world, End events are emitted for all its contacts
+    pub fn remove(&mut self, id: usize) -> Option<Body> {
+        let body = self.bodies.get_mut(id)?.take()?;
+        self.scanner.remove(body.scanned, id);
+        self.free.push(id);
+
+        let events = &mut self.events;
+        self.touching.retain(|pair| {
+            if pair.0 == id || pair.1 == id {
+                events.send(ContactEvent::End(pair.0, pair.1));
+                return false;
+            }
+            true
+        });
+
+        Some(body)
+    }
+
+    #[inline]
+    pub fn get(&self, id: usize) -> Option<&Body> {
+        self.bodies.get(id)?.as_ref()
+    }
+
+    /// get_mut returns body for modification, moving it is fine, scanner is synced on next step
+    #[inline]
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Body> {
+        self.bodies.get_mut(id)?.as_mut()
+    }
+
+    /// contacts returns contacts found in last step
+    #[inline]
+    pub fn contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    /// is_touching returns whether two bodies touched in last step
+    #[inline]
+    pub fn is_touching(&self, a: usize, b: usize) -> bool {
+        self.touching.contains(&if a < b {(a, b)} else {(b, a)})
+    }
+
+    /// events returns contact events, they stay readable until second call of `step` after the
+    /// one that produced them
+    #[inline]
+    pub fn events(&self) -> &Events<ContactEvent> {
+        &self.events
+    }
+
+    #[inline]
+    pub fn events_mut(&mut self) -> &mut Events<ContactEvent> {
+        &mut self.events
+    }
+
+    /// step advances simulation by delta in fixed steps, leftover time is kept for next call
+    pub fn step(&mut self, delta: f32) {
+        self.events.update();
+        self.accumulator += delta;
+        while self.accumulator >= self.time_step {
+            self.accumulator -= self.time_step;
+            self.fixed_step(self.time_step);
+        }
+    }
+
+    fn fixed_step(&mut self, dt: f32) {
+        for body in self.bodies.iter_mut().flatten() {
+            if !body.is_static() {
+                body.vel += (self.gravity + body.force * body.inv_mass) * dt;
+            }
+            body.force = Vect::ZERO;
+        }
+
+        self.sync_scanner();
+        self.find_contacts();
+
+        for _ in 0..self.iterations {
+            for i in 0..self.contacts.len() {
+                let c = self.contacts[i];
+                let (a, b) = self.pair_mut(c.a, c.b);
+                resolve_velocity(a, b, c.normal);
+            }
+        }
+
+        for body in self.bodies.iter_mut().flatten() {
+            if !body.is_static() {
+                body.pos += body.vel * dt;
+            }
+        }
+
+        for i in 0..self.contacts.len() {
+            let c = self.contacts[i];
+            let (correction, slop) = (self.correction, self.slop);
+            let (a, b) = self.pair_mut(c.a, c.b);
+            let total = a.inv_mass + b.inv_mass;
+            let push = c.normal * ((c.depth - slop).max(0f32) / total * correction);
+            a.pos -= push * a.inv_mass;
+            b.pos += push * b.inv_mass;
+        }
+
+        self.sync_scanner();
+
+        for pair in self.touching.iter() {
+            if !self.previous.contains(pair) {
+                self.events.send(ContactEvent::Begin(pair.0, pair.1));
+            }
+        }
+        for pair in self.previous.iter() {
+            if !self.touching.contains(pair) {
+                self.events.send(ContactEvent::End(pair.0, pair.1));
+            }
+        }
+    }
+
+    fn sync_scanner(&mut self) {
+        for (id, body) in self.bodies.iter_mut().enumerate() {
+            if let Some(body) = body {
+                if body.scanned != body.pos {
+                    self.scanner.update(body.scanned, body.pos, id);
+                    body.scanned = body.pos;
+                }
+            }
+        }
+    }
+
+    fn find_contacts(&mut self) {
+        self.contacts.clear();
+        mem::swap(&mut self.touching, &mut self.previous);
+        self.touching.clear();
+
+        let padding = (self.max_extent - self.tile_size.x.min(self.tile_size.y)).max(0f32);
+        for i in 0..self.bodies.len() {
+            let a = match &self.bodies[i] {
+                Some(a) => a,
+                None => continue,
+            };
+            let bounds = a.bounds();
+
+            self.collector.clear();
+            self.scanner.query(&bounds.expanded(padding), &mut self.collector);
+
+            for &j in self.collector.iter() {
+                if j <= i {
+                    continue;
+                }
+                let b = self.bodies[j].as_ref().unwrap();
+                if a.is_static() && b.is_static() || !bounds.intersects(&b.bounds()) {
+                    continue;
+                }
+
+                if let Some((normal, depth)) = collide(a, b, &mut self.verts) {
+                    self.touching.insert((i, j));
+                    if !a.sensor && !b.sensor {
+                        self.contacts.push(Contact { a: i, b: j, normal, depth });
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn pair_mut(&mut self, a: usize, b: usize) -> (&mut Body, &mut Body) {
+        let (low, high) = self.bodies.split_at_mut(b);
+        (low[a].as_mut().unwrap(), high[0].as_mut().unwrap())
+    }
+}
+
+#[inline]
+fn resolve_velocity(a: &mut Body, b: &mut Body, normal: Vect) {
+    let total = a.inv_mass + b.inv_mass;
+    let rv = b.vel - a.vel;
+    let vn = rv.dot(normal);
+    if vn > 0f32 {
+        return;
+    }
+
+    let e = a.restitution.min(b.restitution);
+    let j = -(1f32 + e) * vn / total;
+    a.vel -= normal * (j * a.inv_mass);
+    b.vel += normal * (j * b.inv_mass);
+
+    let rv = b.vel - a.vel;
+    let tangent = (rv - normal * rv.dot(normal)).norm();
+    let mu = (a.friction * b.friction).sqrt();
+    let jt = clamp_f(-rv.dot(tangent) / total, -j * mu, j * mu);
+    a.vel -= tangent * (jt * a.inv_mass);
+    b.vel += tangent * (jt * b.inv_mass);
+}
+
+/// collide returns contact normal pointing from a to b and penetration depth
+pub fn collide(a: &Body, b: &Body, verts: &mut (Vec<Vect>, Vec<Vect>)) -> Option<(Vect, f32)> {
+    match (&a.shape, &b.shape) {
+        (Shape::Circle(ra), Shape::Circle(rb)) => {
+            let d = b.pos - a.pos;
+            let dist = d.len();
+            let r = ra + rb;
+            if dist >= r {
+                return None;
+            }
+            let normal = if dist == 0f32 {Vect::UP} else {d / dist};
+            Some((normal, r - dist))
+        },
+        (Shape::Circle(r), _) => {
+            b.shape.verts(b.pos, &mut verts.1);
+            circle_polygon(a.pos, *r, &verts.1)
+        },
+        (_, Shape::Circle(r)) => {
+            a.shape.verts(a.pos, &mut verts.0);
+            circle_polygon(b.pos, *r, &verts.0).map(|(n, d)| (n.inverted(), d))
+        },
+        _ => {
+            a.shape.verts(a.pos, &mut verts.0);
+            b.shape.verts(b.pos, &mut verts.1);
+            polygon_polygon(&verts.0, &verts.1)
+        }
+    }
+}
+
+/// circle_polygon returns normal pointing from circle to polygon
+fn circle_polygon(c: Vect, r: f32, verts: &[Vect]) -> Option<(Vect, f32)> {
+    let mut closest = verts[0];
+    let mut best = f32::MAX;
+    let mut inside = true;
+    let mut sign = 0f32;
+    for i in 0..verts.len() {
+        let (p, q) = (verts[i], verts[(i + 1) % verts.len()]);
+        let cross = (q - p).cross(c - p);
+        if cross != 0f32 {
+            if sign == 0f32 {
+                sign = cross.signum();
+            } else if sign != cross.signum() {
+                inside = false;
+            }
+        }
+
+        let point = closest_on_segment(c, p, q);
+        let dist = (point - c).len();
+        if dist < best {
+            best = dist;
+            closest = point;
+        }
+    }
+
+    if inside {
+        let d = c - closest;
+        let normal = if best == 0f32 {(Vect::average(verts) - c).norm()} else {d / best};
+        return Some((normal, r + best));
+    }
+
+    if best >= r {
+        return None;
+    }
+
+    Some(((closest - c) / best, r - best))
+}
+
+/// polygon_polygon performs separating axis test
+fn polygon_polygon(a: &[Vect], b: &[Vect]) -> Option<(Vect, f32)> {
+    let mut depth = f32::MAX;
+    let mut normal = Vect::ZERO;
+    for verts in [a, b].iter() {
+        for i in 0..verts.len() {
+            let edge = verts[(i + 1) % verts.len()] - verts[i];
+            let axis = Vect::new(-edge.y, edge.x).norm();
+            let (a_min, a_max) = project(a, axis);
+            let (b_min, b_max) = project(b, axis);
+            let overlap = a_max.min(b_max) - a_min.max(b_min);
+            if overlap <= 0f32 {
+                return None;
+            }
+            if overlap < depth {
+                depth = overlap;
+                normal = axis;
+            }
+        }
+    }
+
+    if normal.dot(Vect::average(b) - Vect::average(a)) < 0f32 {
+        normal = normal.inverted();
+    }
+
+    Some((normal, depth))
+}
+
+#[inline]
+fn project(verts: &[Vect], axis: Vect) -> (f32, f32) {
+    let mut min = f32::MAX;
+    let mut max = f32::MIN;
+    for v in verts {
+        let p = v.dot(axis);
+        min = min.min(p);
+        max = max.max(p);
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::physics::{World, Body, Shape, ContactEvent};
+    use crate::math::vect::Vect;
+
+    #[test]
+    fn rest_test() {
+        let mut world = World::new(10, 10, Vect::new(100f32, 100f32));
+        world.gravity = Vect::new(0f32, -100f32);
+        let ground = world.add(Body::new_static(Vect::new(500f32, 100f32), Shape::Aabb(Vect::new(400f32, 20f32))));
+        let ball = world.add(Body::new(Vect::new(500f32, 200f32), Shape::Circle(10f32), 1f32));
+        let sensor = world.add(Body::new_sensor(Vect::new(500f32, 300f32), Shape::Polygon(vec![
+            Vect::new(-50f32, -50f32), Vect::new(50f32, -50f32), Vect::new(0f32, 50f32)
+        ])));
+
+        world.step(3f32);
+
+        let y = world.get(ball).unwrap().pos.y;
+        assert!((y - 130f32).abs() < 1f32, "{}", y);
+        assert!(world.is_touching(ground, ball));
+        assert!(!world.is_touching(ball, sensor));
+        let events: Vec<_> = world.events_mut().drain().collect();
+        assert_eq!(Some(&ContactEvent::Begin(ground, ball)), events.first());
+    }
+
+    #[test]
+    fn sensor_test() {
+        let mut world = World::new(10, 10, Vect::new(100f32, 100f32));
+        let sensor = world.add(Body::new_sensor(Vect::new(500f32, 500f32), Shape::Circle(50f32)));
+        let body = world.add(Body::new(Vect::new(300f32, 500f32), Shape::Aabb(Vect::new(10f32, 10f32)), 1f32));
+        world.get_mut(body).unwrap().vel = Vect::new(100f32, 0f32);
+        let mut reader = world.events().reader();
+
+        world.step(2f32);
+        assert!(world.get(body).unwrap().pos.x > 490f32);
+        let events: Vec<_> = world.events().read(&mut reader).cloned().collect();
+        assert_eq!(vec![ContactEvent::Begin(sensor, body)], events);
+
+        world.step(2f32);
+        let events: Vec<_> = world.events().read(&mut reader).cloned().collect();
+        assert_eq!(vec![ContactEvent::End(sensor, body)], events);
+    }
+
+    #[test]
+    #[should_panic(expected = "time step has to be positive")]
+    fn time_step_test() {
+        World::new(10, 10, Vect::new(100f32, 100f32)).with_time_step(0f32);
+    }
+}
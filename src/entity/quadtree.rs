@@ -0,0 +1,234 @@
+use crate::math::rect::Rect;
+use crate::math::vect::Vect;
+use crate::entity::spatial::SpatialIndex;
+
+struct Node<T> {
+    loose: Rect,
+    items: Vec<(Rect, T)>,
+    children: Option<usize>,
+    depth: usize,
+}
+
+impl<T> Node<T> {
+    #[inline]
+    fn new(bounds: Rect, depth: usize) -> Self {
+        Self {
+            loose: bounds.expanded(bounds.width().max(bounds.height()) / 2f32),
+            items: vec![],
+            children: None,
+            depth,
+        }
+    }
+}
+
+/// QuadTree is loose quadtree. Every node accepts items that fit into its bounds doubled in size
+/// so item is always stored at one node and never has to be split between children. Items outside
+/// of the root bounds are kept in root.
+pub struct QuadTree<T: PartialEq + Copy> {
+    nodes: Vec<Node<T>>,
+    bounds: Vec<Rect>,
+    capacity: usize,
+    max_depth: usize,
+    count: usize,
+}
+
+impl<T: PartialEq + Copy> QuadTree<T> {
+    /// new creates quadtree covering bounds, node is split when it holds more then capacity
+    /// items unless it is max_depth deep
+    pub fn new(bounds: Rect, capacity: usize, max_depth: usize) -> Self {
+        Self {
+            nodes: vec![Node::new(bounds, 0)],
+            bounds: vec![bounds],
+            capacity,
+            max_depth,
+            count: 0,
+        }
+    }
+
+    /// len returns count of stored items
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// clear removes all items but keeps the node structure
+    pub fn clear(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.items.clear();
+        }
+        self.count = 0;
+    }
+
+    #[inline]
+    fn child_for(&self, node: usize, bounds: &Rect) -> Option<usize> {
+        let first = self.nodes[node].children?;
+        let center = self.bounds[node].center();
+        let c = bounds.center();
+        let child = first + if c.x < center.x {0} else {1} + if c.y < center.y {0} else {2};
+        if bounds.fits_in(&self.nodes[child].loose) {
+            Some(child)
+        } else {
+            None
+        }
+    }
+
+    fn split(&mut self, node: usize) {
+        let b = self.bounds[node];
+        let c = b.center();
+        let depth = self.nodes[node].depth + 1;
+        let first = self.nodes.len();
+        for r in [
+            Rect::new(b.min.x, b.min.y, c.x, c.y),
+            Rect::new(c.x, b.min.y, b.max.x, c.y),
+            Rect::new(b.min.x, c.y, c.x, b.max.y),
+            Rect::new(c.x, c.y, b.max.x, b.max.y),
+        ].iter() {
+            self.nodes.push(Node::new(*r, depth));
+            self.bounds.push(*r);
+        }
+        self.nodes[node].children = Some(first);
+
+        let items = std::mem::take(&mut self.nodes[node].items);
+        for (bounds, id) in items {
+            match self.child_for(node, &bounds) {
+                Some(child) => self.nodes[child].items.push((bounds, id)),
+                None => self.nodes[node].items.push((bounds, id)),
+            }
+        }
+    }
+
+    #[inline]
+    fn collect_rect(&self, rect: &Rect, collector: &mut Vec<T>) {
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            for (bounds, id) in node.items.iter() {
+                if bounds.intersects(rect) {
+                    collector.push(*id);
+                }
+            }
+            if let Some(first) = node.children {
+                for child in first..first + 4 {
+                    if self.nodes[child].loose.intersects(rect) {
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: PartialEq + Copy> SpatialIndex<T> for QuadTree<T> {
+    fn insert(&mut self, bounds: Rect, id: T) {
+        let mut node = 0;
+        while let Some(child) = self.child_for(node, &bounds) {
+            node = child;
+        }
+
+        self.nodes[node].items.push((bounds, id));
+        self.count += 1;
+
+        if self.nodes[node].children.is_none() && self.nodes[node].items.len() > self.capacity &&
+            self.nodes[node].depth < self.max_depth {
+            self.split(node);
+        }
+    }
+
+    fn remove(&mut self, bounds: Rect, id: T) -> bool {
+        let mut path = vec![0];
+        while let Some(child) = self.child_for(path[path.len() - 1], &bounds) {
+            path.push(child);
+        }
+
+        for node in path.into_iter().rev() {
+            let items = &mut self.nodes[node].items;
+            if let Some(i) = items.iter().position(|item| item.1 == id) {
+                items.swap_remove(i);
+                self.count -= 1;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn update(&mut self, old: Rect, new: Rect, id: T) -> bool {
+        if !self.remove(old, id) {
+            return false;
+        }
+        self.insert(new, id);
+        true
+    }
+
+    #[inline]
+    fn query_rect(&self, rect: &Rect, collector: &mut Vec<T>) {
+        self.collect_rect(rect, collector);
+    }
+
+    #[inline]
+    fn query_point(&self, pos: Vect, collector: &mut Vec<T>) {
+        self.collect_rect(&Rect { min: pos, max: pos }, collector);
+    }
+
+    fn raycast(&self, from: Vect, to: Vect, collector: &mut Vec<T>) {
+        let mut hits = vec![];
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            for (bounds, id) in node.items.iter() {
+                if let Some(t) = bounds.ray_cast(from, to) {
+                    hits.push((t, *id));
+                }
+            }
+            if let Some(first) = node.children {
+                for child in first..first + 4 {
+                    if self.nodes[child].loose.ray_cast(from, to).is_some() {
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        collector.extend(hits.iter().map(|h| h.1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::quadtree::QuadTree;
+    use crate::entity::spatial::SpatialIndex;
+    use crate::math::rect::Rect;
+    use crate::math::vect::Vect;
+    use rand::Rng;
+
+    #[test]
+    fn query_test() {
+        let mut tree = QuadTree::new(Rect::new(0f32, 0f32, 1000f32, 1000f32), 4, 8);
+        let mut rng = rand::thread_rng();
+        let mut rects = vec![];
+        for i in 0..500 {
+            let r = Rect::cube(Vect::new(rng.gen::<f32>() * 1000f32, rng.gen::<f32>() * 1000f32), rng.gen::<f32>() * 30f32);
+            tree.insert(r, i);
+            rects.push(r);
+        }
+
+        for (i, rect) in rects[..250].iter().enumerate() {
+            assert!(tree.remove(*rect, i));
+        }
+        assert_eq!(250, tree.len());
+
+        let query = Rect::new(200f32, 300f32, 600f32, 500f32);
+        let mut collector = vec![];
+        tree.query_rect(&query, &mut collector);
+        collector.sort();
+
+        let expected: Vec<usize> = (250..500).filter(|i| rects[*i].intersects(&query)).collect();
+        assert_eq!(expected, collector);
+    }
+}
@@ -3,12 +3,80 @@ use crate::math::vect::Vect;
 use std::collections::HashSet;
 use std::hash::BuildHasherDefault;
 use hashers::fnv::FNV1aHasher32;
-use crate::entity::{FastHash};
+use crate::entity::{FastHash, gen_hash};
+use crate::entity::spatial::SpatialIndex;
+use crate::entity::grid::Grid;
 use crate::math::clamp;
 use std::hash::Hash;
+use std::thread;
 
+/// Command is deferred change to the Scanner recorded during parallel phase
+#[derive(Copy, Clone, Debug)]
+pub enum Command<T> {
+    Insert(Vect, T),
+    Remove(Vect, T),
+    Update(Vect, Vect, T),
+}
+
+/// Commands collects changes that cannot be applied while Scanner is shared between threads.
+/// Apply them all at once with `Scanner::apply` after the parallel phase is over.
+pub struct Commands<T> {
+    buffer: Vec<Command<T>>,
+}
+
+impl<T> Default for Commands<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Commands<T> {
+    #[inline]
+    pub fn new() -> Self {
+        Self { buffer: vec![] }
+    }
+
+    #[inline]
+    pub fn insert(&mut self, pos: Vect, id: T) {
+        self.buffer.push(Command::Insert(pos, id));
+    }
+
+    #[inline]
+    pub fn remove(&mut self, pos: Vect, id: T) {
+        self.buffer.push(Command::Remove(pos, id));
+    }
+
+    #[inline]
+    pub fn update(&mut self, old: Vect, new: Vect, id: T) {
+        self.buffer.push(Command::Update(old, new, id));
+    }
+
+    /// append moves all commands from other to self
+    #[inline]
+    pub fn append(&mut self, other: &mut Commands<T>) {
+        self.buffer.append(&mut other.buffer);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+/// Scanner is uniform grid of cells that stores ids by position. All query methods take `&self`
+/// and Scanner is `Sync` as long as `T` is, so `&Scanner` itself is the read view you share
+/// between worker threads. Nothing can mutate it while it is borrowed, queries are lock-free.
+/// Changes made during the parallel phase should be recorded into `Commands` and applied
+/// afterwards with `apply`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Raw<T>"))]
 pub struct Scanner<T: Hash + Eq + Copy + Clone> {
-    pub map: Vec<Vec<HashSet<T, FastHash>>>,
+    pub map: Grid<HashSet<T, FastHash>>,
     tile_size: Vect,
     w: usize,
     h: usize,
@@ -16,7 +84,7 @@ pub struct Scanner<T: Hash + Eq + Copy + Clone> {
 
 impl<T: Hash + Eq + Copy + Clone> Scanner<T> {
     pub fn new(w: usize, h: usize, tile_size: Vect) -> Self {
-        Scanner{map: vec![vec![HashSet::with_hasher(BuildHasherDefault::<FNV1aHasher32>::default()); w]; h], tile_size, w, h}
+        Scanner{map: Grid::new(w, h, HashSet::with_hasher(BuildHasherDefault::<FNV1aHasher32>::default())), tile_size, w, h}
     }
 
     #[inline]
@@ -28,22 +96,20 @@ impl<T: Hash + Eq + Copy + Clone> Scanner<T> {
     #[inline]
     pub fn insert(&mut self, pos: Vect, id: T) {
         let (x, y) = self.get_coord(pos);
-        self.map[y][x].insert(id);
+        self.map[(x, y)].insert(id);
     }
 
     #[inline]
     pub fn remove(&mut self, pos: Vect, id: T) -> bool {
         let (x, y) = self.get_coord(pos);
-        self.map[y][x].remove(&id)
+        self.map[(x, y)].remove(&id)
 
     }
 
     pub fn slow_remove(&mut self, id: T) -> bool {
-        for row in self.map.iter_mut() {
-            for tile in row.iter_mut() {
-                if tile.remove(&id) {
-                    return true;
-                }
+        for tile in self.map.iter_mut() {
+            if tile.remove(&id) {
+                return true;
             }
         }
 
@@ -60,11 +126,11 @@ impl<T: Hash + Eq + Copy + Clone> Scanner<T> {
             return true;
         }
 
-        if !self.map[old.1][old.0].remove(&id) {
+        if !self.map[old].remove(&id) {
             return false;
         }
 
-        self.map[new.1][new.0].insert(id);
+        self.map[new].insert(id);
         true
     }
 
@@ -79,36 +145,207 @@ impl<T: Hash + Eq + Copy + Clone> Scanner<T> {
         max = (clamp(max.0+2, 0, self.w),clamp(max.1+2, 0, self.h));
         for y in min.1..max.1 {
             for x in min.0..max.0 {
-                collector.extend(&self.map[y][x]);
+                collector.extend(&self.map[(x, y)]);
             }
         }
     }
 
     #[inline]
     pub fn query_point(&self, pos: Vect, collector: &mut Vec<T>) {
-        let pos = self.get_coord(pos);
+        self.query_cell(self.get_coord(pos), collector);
+    }
+
+    /// apply applies all deferred commands in order they were recorded and leaves commands empty
+    pub fn apply(&mut self, commands: &mut Commands<T>) {
+        for command in commands.buffer.drain(..) {
+            match command {
+                Command::Insert(pos, id) => self.insert(pos, id),
+                Command::Remove(pos, id) => {
+                    self.remove(pos, id);
+                },
+                Command::Update(old, new, id) => {
+                    self.update(old, new, id);
+                },
+            }
+        }
+    }
+
+    /// par_for_each_neighbourhood splits grid to horizontal stripes, one per worker thread, and
+    /// calls f for every non-empty cell with cell content and all ids from 3 x 3 cells around it
+    /// (cell itself included). Each worker records changes to its own Commands, they are merged
+    /// in stripe order and returned so you can `apply` them once all workers are done.
+    pub fn par_for_each_neighbourhood<F>(&self, workers: usize, f: F) -> Commands<T>
+        where T: Send + Sync, F: Fn(&HashSet<T, FastHash>, &[T], &mut Commands<T>) + Sync {
+        let workers = clamp(workers, 1, self.h);
+        let stripe = self.h.div_ceil(workers);
+        let f = &f;
+
+        let mut results = thread::scope(|s| {
+            let handles: Vec<_> = (0..workers).map(|i| {
+                s.spawn(move || {
+                    let mut commands = Commands::new();
+                    let mut collector = vec![];
+                    for y in i * stripe..((i + 1) * stripe).min(self.h) {
+                        for x in 0..self.w {
+                            let cell = &self.map[(x, y)];
+                            if cell.is_empty() {
+                                continue;
+                            }
+                            collector.clear();
+                            self.query_cell((x, y), &mut collector);
+                            f(cell, &collector, &mut commands);
+                        }
+                    }
+                    commands
+                })
+            }).collect();
+
+            handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+        });
+
+        let mut commands = Commands::new();
+        for result in results.iter_mut() {
+            commands.append(result);
+        }
+
+        commands
+    }
+
+    #[inline]
+    fn query_cell(&self, pos: (usize, usize), collector: &mut Vec<T>) {
         let min = (
-            if pos.0 == 0 {0} else {clamp(pos.0-1, 0, self.w)},
-            if pos.1 == 0 {0} else {clamp(pos.1-1, 0, self.h)}
+            if pos.0 == 0 {0} else {pos.0-1},
+            if pos.1 == 0 {0} else {pos.1-1}
         );
         let max = (clamp(pos.0+2, 0, self.w),clamp(pos.1+2, 0, self.h));
 
         for y in min.1..max.1 {
             for x in min.0..max.0 {
-                collector.extend(&self.map[y][x]);
+                collector.extend(&self.map[(x, y)]);
             }
         }
     }
 
-    pub fn get_shape_count(&self) -> usize {
-        let mut count = 0;
-        for row in self.map.iter() {
-            for tile in row.iter() {
-                count += tile.len();
+    /// walk_cells calls f for every cell that segment from `from` to `to` passes trough, cells
+    /// are visited in order from `from`
+    pub(crate) fn walk_cells<F: FnMut(usize, usize)>(&self, from: Vect, to: Vect, mut f: F) {
+        let start = from / self.tile_size;
+        let end = to / self.tile_size;
+        let (mut x, mut y) = self.get_coord(from);
+        let last = self.get_coord(to);
+        let d = end - start;
+        let step = (if d.x < 0f32 {-1} else {1}, if d.y < 0f32 {-1i32} else {1});
+        let delta = Vect::new((1f32 / d.x).abs(), (1f32 / d.y).abs());
+        let mut t_max = Vect::new(
+            if d.x < 0f32 {start.x - start.x.floor()} else {start.x.floor() + 1f32 - start.x} * delta.x,
+            if d.y < 0f32 {start.y - start.y.floor()} else {start.y.floor() + 1f32 - start.y} * delta.y,
+        );
+
+        f(x, y);
+        while (x, y) != last {
+            if t_max.x < t_max.y {
+                if x == 0 && step.0 < 0 || x == self.w - 1 && step.0 > 0 {
+                    break;
+                }
+                x = (x as i32 + step.0) as usize;
+                t_max.x += delta.x;
+            } else {
+                if y == 0 && step.1 < 0 || y == self.h - 1 && step.1 > 0 {
+                    break;
+                }
+                y = (y as i32 + step.1) as usize;
+                t_max.y += delta.y;
             }
+            f(x, y);
         }
+    }
 
-        count
+    /// query_sweep collects candidates for continuous collision of rect moving by delta, it
+    /// visits only cells along the motion path so it stays cheap for fast projectiles
+    pub fn query_sweep(&self, rect: &Rect, delta: Vect, collector: &mut Vec<T>) {
+        let c = rect.center();
+        let pad = (
+            (rect.width() / 2f32 / self.tile_size.x).ceil() as usize + 1,
+            (rect.height() / 2f32 / self.tile_size.y).ceil() as usize + 1,
+        );
+        self.query_along(c, c + delta, pad, collector);
+    }
+
+    fn query_along(&self, from: Vect, to: Vect, pad: (usize, usize), collector: &mut Vec<T>) {
+        let mut visited = HashSet::with_hasher(gen_hash());
+        self.walk_cells(from, to, |x, y| {
+            for cy in y.saturating_sub(pad.1)..clamp(y+pad.1+1, 0, self.h) {
+                for cx in x.saturating_sub(pad.0)..clamp(x+pad.0+1, 0, self.w) {
+                    if visited.insert((cx, cy)) {
+                        collector.extend(&self.map[(cx, cy)]);
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn get_shape_count(&self) -> usize {
+        self.map.iter().map(|tile| tile.len()).sum()
+    }
+}
+
+/// Raw is Scanner as it is deserialized, before its size and tile size are checked
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct Raw<T: Hash + Eq + Copy + Clone> {
+    map: Grid<HashSet<T, FastHash>>,
+    tile_size: Vect,
+    w: usize,
+    h: usize,
+}
+
+#[cfg(feature = "serde")]
+impl<T: Hash + Eq + Copy + Clone> std::convert::TryFrom<Raw<T>> for Scanner<T> {
+    type Error = String;
+
+    fn try_from(raw: Raw<T>) -> Result<Self, Self::Error> {
+        if raw.map.size() != (raw.w, raw.h) || raw.w == 0 || raw.h == 0 {
+            return Err(format!("scanner of size {}x{} cannot have map of size {:?}", raw.w, raw.h, raw.map.size()));
+        }
+        // comparisons also reject NaN
+        if !(raw.tile_size.x > 0f32 && raw.tile_size.y > 0f32) {
+            return Err(format!("tile size {:?} has to be positive", raw.tile_size));
+        }
+        Ok(Self { map: raw.map, tile_size: raw.tile_size, w: raw.w, h: raw.h })
+    }
+}
+
+/// Scanner stores just positions so bounds are reduced to their center. As query also checks
+/// neighbour cells, bounds should not be bigger then tile.
+impl<T: Hash + Eq + Copy + Clone> SpatialIndex<T> for Scanner<T> {
+    #[inline]
+    fn insert(&mut self, bounds: Rect, id: T) {
+        Scanner::insert(self, bounds.center(), id);
+    }
+
+    #[inline]
+    fn remove(&mut self, bounds: Rect, id: T) -> bool {
+        Scanner::remove(self, bounds.center(), id)
+    }
+
+    #[inline]
+    fn update(&mut self, old: Rect, new: Rect, id: T) -> bool {
+        Scanner::update(self, old.center(), new.center(), id)
+    }
+
+    #[inline]
+    fn query_rect(&self, rect: &Rect, collector: &mut Vec<T>) {
+        self.query(rect, collector);
+    }
+
+    #[inline]
+    fn query_point(&self, pos: Vect, collector: &mut Vec<T>) {
+        Scanner::query_point(self, pos, collector);
+    }
+
+    #[inline]
+    fn raycast(&self, from: Vect, to: Vect, collector: &mut Vec<T>) {
+        self.query_along(from, to, (1, 1), collector);
     }
 }
 
@@ -117,6 +354,8 @@ mod tests {
     use crate::math::vect::Vect;
     use rand::Rng;
     use crate::entity::scanner::Scanner;
+    use crate::entity::spatial::SpatialIndex;
+    use crate::math::rect::Rect;
 
     #[test]
     fn insert_test() {
@@ -148,4 +387,65 @@ mod tests {
 
         assert_eq!(0 as usize, map.get_shape_count());
     }
+
+    #[test]
+    fn par_for_each_neighbourhood_test() {
+        let mut map: Scanner<usize> = Scanner::new(10, 10, Vect::new(100f32, 100f32));
+        for i in 0..100 {
+            map.insert(Vect::new((i % 10) as f32 * 100f32 + 50f32, (i / 10) as f32 * 100f32 + 50f32), i);
+        }
+
+        let mut commands = map.par_for_each_neighbourhood(4, |cell, neighbours, commands| {
+            assert!(neighbours.len() >= 4 && neighbours.len() <= 9);
+            for id in cell.iter() {
+                if id % 2 == 0 {
+                    commands.remove(Vect::new((id % 10) as f32 * 100f32 + 50f32, (id / 10) as f32 * 100f32 + 50f32), *id);
+                }
+            }
+        });
+
+        assert_eq!(50, commands.len());
+        map.apply(&mut commands);
+        assert!(commands.is_empty());
+        assert_eq!(50, map.get_shape_count());
+    }
+
+    #[test]
+    fn raycast_test() {
+        let mut map: Scanner<usize> = Scanner::new(10, 10, Vect::new(100f32, 100f32));
+        map.insert(Vect::new(950f32, 950f32), 0);
+        map.insert(Vect::new(50f32, 950f32), 1);
+        map.insert(Vect::new(550f32, 450f32), 2);
+
+        let mut collector = vec![];
+        SpatialIndex::raycast(&map, Vect::new(10f32, 10f32), Vect::new(990f32, 990f32), &mut collector);
+        collector.sort();
+        assert_eq!(vec![0, 2], collector);
+
+        collector.clear();
+        map.query_sweep(&Rect::cube(Vect::new(50f32, 50f32), 10f32), Vect::new(900f32, 0f32), &mut collector);
+        assert!(collector.is_empty());
+        map.query_sweep(&Rect::cube(Vect::new(50f32, 350f32), 10f32), Vect::new(900f32, 0f32), &mut collector);
+        assert_eq!(vec![2], collector);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_test() {
+        let mut map: Scanner<usize> = Scanner::new(4, 3, Vect::new(10f32, 10f32));
+        map.insert(Vect::new(15f32, 25f32), 7);
+        let text = serde_json::to_string(&map).unwrap();
+        let restored: Scanner<usize> = serde_json::from_str(&text).unwrap();
+        let mut collector = vec![];
+        restored.query_point(Vect::new(15f32, 25f32), &mut collector);
+        assert_eq!(vec![7], collector);
+
+        // size of the scanner itself, map comes first
+        let at = text.rfind("\"w\":4").unwrap();
+        let broken = format!("{}\"w\":5{}", &text[..at], &text[at + 5..]);
+        let error = serde_json::from_str::<Scanner<usize>>(&broken).err().unwrap();
+        assert!(error.to_string().contains("cannot have map"));
+        let broken = text.replacen("\"tile_size\":{\"x\":10.0", "\"tile_size\":{\"x\":0.0", 1);
+        assert!(broken != text && serde_json::from_str::<Scanner<usize>>(&broken).is_err());
+    }
 }
@@ -0,0 +1,395 @@
+use crate::entity::storage::Entity;
+use crate::entity::world::{Access, Bundle, Component, Query, World};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+type Command = Box<dyn FnOnce(&mut World) + Send>;
+type Run = Box<dyn FnMut(&World, &mut Commands) + Send>;
+
+/// Commands records changes of world structure made while systems run, they are applied
+/// between stages so systems can share the world
+#[derive(Default)]
+pub struct Commands {
+    queue: Vec<Command>,
+}
+
+impl Commands {
+    #[inline]
+    pub fn new() -> Self {
+        Self { queue: vec![] }
+    }
+
+    pub fn spawn<B: Bundle + Send + 'static>(&mut self, bundle: B) {
+        self.add(move |world| {
+            world.spawn(bundle);
+        });
+    }
+
+    pub fn despawn(&mut self, entity: Entity) {
+        self.add(move |world| {
+            world.despawn(entity);
+        });
+    }
+
+    pub fn insert<T: Component>(&mut self, entity: Entity, component: T) {
+        self.add(move |world| {
+            world.insert(entity, component);
+        });
+    }
+
+    pub fn remove<T: Component>(&mut self, entity: Entity) {
+        self.add(move |world| {
+            world.remove::<T>(entity);
+        });
+    }
+
+    /// add records arbitrary change
+    #[inline]
+    pub fn add<F: FnOnce(&mut World) + Send + 'static>(&mut self, f: F) {
+        self.queue.push(Box::new(f));
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// apply applies all changes in order they were recorded
+    pub fn apply(&mut self, world: &mut World) {
+        for command in self.queue.drain(..) {
+            command(world);
+        }
+    }
+}
+
+/// System is named function over the world that declares which components and resources it
+/// reads and writes. Declarations have to be complete, system borrowing something it did not
+/// declare can panic when it runs in parallel with a system that writes it.
+pub struct System {
+    name: String,
+    access: Access,
+    after: Vec<String>,
+    run: Run,
+    commands: Commands,
+}
+
+impl System {
+    pub fn new<F: FnMut(&World, &mut Commands) + Send + 'static>(name: &str, run: F) -> Self {
+        Self { name: name.to_string(), access: Access::default(), after: vec![], run: Box::new(run), commands: Commands::new() }
+    }
+
+    /// reads declares shared access to component or resource
+    #[inline]
+    pub fn reads<T: 'static>(mut self) -> Self {
+        self.access.read::<T>();
+        self
+    }
+
+    /// writes declares exclusive access to component or resource
+    #[inline]
+    pub fn writes<T: 'static>(mut self) -> Self {
+        self.access.write::<T>();
+        self
+    }
+
+    /// query declares access of query, `query::<(&mut Pos, &Vel)>()` is the same as
+    /// `writes::<Pos>().reads::<Vel>()`
+    #[inline]
+    pub fn query<Q: Query>(mut self) -> Self {
+        Q::access(&mut self.access);
+        self
+    }
+
+    /// after makes system run after system of given name from the same stage
+    #[inline]
+    pub fn after(mut self, name: &str) -> Self {
+        self.after.push(name.to_string());
+        self
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+struct Stage {
+    name: String,
+    systems: Vec<System>,
+    batches: Option<Vec<Vec<usize>>>,
+}
+
+impl Stage {
+    /// plan splits systems to batches that run one after another, systems in one batch do
+    /// not conflict and all systems they run after are in earlier batches. Conflicting systems
+    /// keep order they were added in. It panics if system runs after system that is not in
+    /// the stage.
+    fn plan(&mut self) {
+        let Self { name, systems, batches } = self;
+        batches.get_or_insert_with(|| {
+            let index = |system: &System, after: &str| systems.iter()
+                .position(|s| s.name == after)
+                .unwrap_or_else(|| panic!("system {} of stage {} runs after unknown system {}", system.name, name, after));
+            let dependencies: Vec<Vec<usize>> = systems.iter()
+                .map(|s| s.after.iter().map(|n| index(s, n)).collect())
+                .collect();
+
+            // ordering by constraints, ties are resolved by order of adding
+            let mut order = vec![];
+            let mut done = vec![false; systems.len()];
+            while order.len() < systems.len() {
+                let next = (0..systems.len())
+                    .find(|&i| !done[i] && dependencies[i].iter().all(|&d| done[d]))
+                    .unwrap_or_else(|| panic!("systems of stage {} have cyclic ordering", name));
+                done[next] = true;
+                order.push(next);
+            }
+
+            let mut levels = vec![0; systems.len()];
+            for (k, &i) in order.iter().enumerate() {
+                levels[i] = order[..k].iter()
+                    .filter(|&&j| dependencies[i].contains(&j) || systems[i].access.conflicts(&systems[j].access))
+                    .map(|&j| levels[j] + 1)
+                    .max()
+                    .unwrap_or(0);
+            }
+
+            let mut plan = vec![vec![]; levels.iter().max().map_or(0, |l| l + 1)];
+            for i in order {
+                plan[levels[i]].push(i);
+            }
+            plan
+        });
+    }
+}
+
+/// Schedule runs systems in stages. Systems of a stage that do not conflict run in parallel
+/// on pool of worker threads that lives as long as the schedule, commands they record are
+/// applied after the stage ends in order the systems were added.
+pub struct Schedule {
+    stages: Vec<Stage>,
+    pool: Pool,
+}
+
+impl Schedule {
+    /// new creates schedule with given count of worker threads, with one worker systems run
+    /// on calling thread
+    pub fn new(workers: usize) -> Self {
+        Self { stages: vec![], pool: Pool::new(workers) }
+    }
+
+    /// with_stage appends stage
+    #[inline]
+    pub fn with_stage(mut self, name: &str) -> Self {
+        self.add_stage(name);
+        self
+    }
+
+    #[inline]
+    pub fn add_stage(&mut self, name: &str) {
+        self.stages.push(Stage { name: name.to_string(), systems: vec![], batches: None });
+    }
+
+    /// add_system adds system to stage of given name, it panics if there is no such stage
+    pub fn add_system(&mut self, stage: &str, system: System) {
+        let stage = self.stages.iter_mut()
+            .find(|s| s.name == stage)
+            .unwrap_or_else(|| panic!("there is no stage {}", stage));
+        stage.systems.push(system);
+        stage.batches = None;
+    }
+
+    /// batches returns names of systems of stage grouped the way they run
+    pub fn batches(&mut self, stage: &str) -> Vec<Vec<&str>> {
+        let stage = match self.stages.iter_mut().find(|s| s.name == stage) {
+            Some(stage) => stage,
+            None => return vec![],
+        };
+        stage.plan();
+        let stage: &Stage = stage;
+        stage.batches.as_ref().unwrap().iter()
+            .map(|b| b.iter().map(|&i| stage.systems[i].name.as_str()).collect())
+            .collect()
+    }
+
+    /// run runs all stages once
+    pub fn run(&mut self, world: &mut World) {
+        let Self { stages, pool } = self;
+        for stage in stages.iter_mut() {
+            stage.plan();
+            let Stage { systems, batches, .. } = stage;
+            for batch in batches.as_ref().unwrap().iter() {
+                let mut members: Vec<&mut System> = systems.iter_mut()
+                    .enumerate()
+                    .filter(|(i, _)| batch.contains(i))
+                    .map(|(_, s)| s)
+                    .collect();
+                pool.run(&mut members, world);
+            }
+
+            for system in systems.iter_mut() {
+                system.commands.apply(world);
+            }
+        }
+    }
+}
+
+/// Task is part of batch sent to worker, pointers stay valid because `Pool::run` does not
+/// return until all its tasks are done
+struct Task {
+    systems: Vec<*mut System>,
+    world: *const World,
+    done: Sender<thread::Result<()>>,
+}
+
+// systems of a task are not shared with other tasks and World is Sync
+unsafe impl Send for Task {}
+
+/// Pool is set of worker threads that run tasks until pool is dropped
+struct Pool {
+    sender: Option<Sender<Task>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    fn new(workers: usize) -> Self {
+        if workers <= 1 {
+            return Self { sender: None, handles: vec![] };
+        }
+
+        let (sender, receiver) = channel();
+        let receiver: Arc<Mutex<Receiver<Task>>> = Arc::new(Mutex::new(receiver));
+        let handles = (0..workers).map(|_| {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || loop {
+                let task = receiver.lock().unwrap().recv();
+                let task = match task {
+                    Ok(task) => task,
+                    Err(_) => return,
+                };
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    for &system in task.systems.iter() {
+                        // safety: see Task
+                        let (system, world) = unsafe { (&mut *system, &*task.world) };
+                        (system.run)(world, &mut system.commands);
+                    }
+                }));
+                let _ = task.done.send(result);
+            })
+        }).collect();
+
+        Self { sender: Some(sender), handles }
+    }
+
+    /// run runs systems spread over workers and waits for all of them, panic of a system is
+    /// resumed on calling thread once the rest is done
+    fn run(&mut self, systems: &mut [&mut System], world: &World) {
+        let sender = match &self.sender {
+            Some(sender) if systems.len() > 1 => sender,
+            _ => {
+                for system in systems.iter_mut() {
+                    (system.run)(world, &mut system.commands);
+                }
+                return;
+            },
+        };
+
+        let chunk = systems.len().div_ceil(self.handles.len());
+        let (done, results) = channel();
+        let mut count = 0;
+        for part in systems.chunks_mut(chunk) {
+            let systems = part.iter_mut().map(|s| &mut **s as *mut System).collect();
+            sender.send(Task { systems, world, done: done.clone() }).unwrap();
+            count += 1;
+        }
+
+        let mut failure = None;
+        for _ in 0..count {
+            if let Err(payload) = results.recv().unwrap() {
+                failure = Some(payload);
+            }
+        }
+        if let Some(payload) = failure {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.sender = None;
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::schedule::{Schedule, System};
+    use crate::entity::world::World;
+
+    struct Pos(f32);
+    struct Vel(f32);
+    struct Age(usize);
+    struct Count(usize);
+
+    #[test]
+    fn run_test() {
+        let mut world = World::new();
+        for i in 0..100 {
+            world.spawn((Pos(0f32), Vel(i as f32), Age(0)));
+        }
+        world.insert_resource(Count(0));
+
+        let mut schedule = Schedule::new(4).with_stage("update").with_stage("cleanup");
+        schedule.add_system("update", System::new("count", |world, _| {
+            let count = world.query::<&Pos>().iter().count();
+            world.resource_mut::<Count>().unwrap().0 = count;
+        }).query::<&Pos>().writes::<Count>().after("move"));
+        schedule.add_system("update", System::new("move", |world, _| {
+            for (_, (pos, vel)) in world.query::<(&mut Pos, &Vel)>().iter() {
+                pos.0 += vel.0;
+            }
+        }).query::<(&mut Pos, &Vel)>());
+        schedule.add_system("update", System::new("age", |world, _| {
+            for (_, age) in world.query::<&mut Age>().iter() {
+                age.0 += 1;
+            }
+        }).query::<&mut Age>());
+        schedule.add_system("cleanup", System::new("despawn", |world, commands| {
+            for (entity, pos) in world.query::<&Pos>().iter() {
+                if pos.0 >= 50f32 {
+                    commands.despawn(entity);
+                }
+            }
+            commands.spawn((Pos(0f32), Vel(0f32), Age(0)));
+        }).reads::<Pos>());
+
+        assert_eq!(vec![vec!["move", "age"], vec!["count"]], schedule.batches("update"));
+
+        schedule.run(&mut world);
+        assert_eq!(100, world.resource::<Count>().unwrap().0);
+        assert_eq!(51, world.len());
+        schedule.run(&mut world);
+        assert_eq!(51, world.resource::<Count>().unwrap().0);
+        assert_eq!(27, world.len());
+        assert_eq!(25, world.query::<&Age>().iter().filter(|(_, a)| a.0 == 2).count());
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown system mvoe")]
+    fn unknown_after_test() {
+        let mut schedule = Schedule::new(1).with_stage("update");
+        schedule.add_system("update", System::new("move", |_, _| {}));
+        schedule.add_system("update", System::new("count", |_, _| {}).after("mvoe"));
+        schedule.batches("update");
+    }
+}
@@ -0,0 +1,30 @@
+use crate::math::rect::Rect;
+use crate::math::vect::Vect;
+
+/// SpatialIndex is common interface of spatial structures so game code can swap them without
+/// rewriting. Scanner is fastest when entities are about the same size and spread evenly,
+/// QuadTree handles clustered entities and Bvh is best when sizes vary a lot.
+///
+/// Scanner does not store bounds so its queries return all candidates from touched cells,
+/// QuadTree and Bvh return only ids whose bounds really overlap the query.
+pub trait SpatialIndex<T> {
+    /// insert adds id with its bounds
+    fn insert(&mut self, bounds: Rect, id: T);
+
+    /// remove removes id, bounds has to be the same as the last bounds id was inserted or
+    /// updated with, returns whether id was found
+    fn remove(&mut self, bounds: Rect, id: T) -> bool;
+
+    /// update moves id from old bounds to new, returns whether id was found
+    fn update(&mut self, old: Rect, new: Rect, id: T) -> bool;
+
+    /// query_rect collects ids overlapping rect
+    fn query_rect(&self, rect: &Rect, collector: &mut Vec<T>);
+
+    /// query_point collects ids containing pos
+    fn query_point(&self, pos: Vect, collector: &mut Vec<T>);
+
+    /// raycast collects ids hit by segment from `from` to `to`, ordered from closest when index
+    /// knows the bounds
+    fn raycast(&self, from: Vect, to: Vect, collector: &mut Vec<T>);
+}
@@ -0,0 +1,456 @@
+use crate::entity::scanner::Scanner;
+use crate::math::rect::Rect;
+use crate::math::vect::Vect;
+use crate::math::clamp;
+use rand::Rng;
+use std::thread;
+
+/// Agent is moving body that steering behaviours work with. Behaviours return force, `apply`
+/// then limits it by max_force and resulting velocity by max_speed.
+#[derive(Copy, Clone, Debug)]
+pub struct Agent {
+    pub pos: Vect,
+    pub vel: Vect,
+    pub radius: f32,
+    pub max_speed: f32,
+    pub max_force: f32,
+}
+
+impl Agent {
+    pub fn new(pos: Vect, radius: f32, max_speed: f32, max_force: f32) -> Self {
+        Self { pos, vel: Vect::ZERO, radius, max_speed, max_force }
+    }
+
+    /// apply accelerates agent by force and moves it
+    #[inline]
+    pub fn apply(&mut self, force: Vect, delta: f32) {
+        self.vel = truncate(self.vel + truncate(force, self.max_force) * delta, self.max_speed);
+        self.pos += self.vel * delta;
+    }
+}
+
+/// truncate shortens vector to max length
+#[inline]
+pub fn truncate(v: Vect, max: f32) -> Vect {
+    let len = v.len();
+    if len > max {
+        v * (max / len)
+    } else {
+        v
+    }
+}
+
+/// seek steers agent towards target at full speed
+#[inline]
+pub fn seek(agent: &Agent, target: Vect) -> Vect {
+    agent.pos.to(target).norm() * agent.max_speed - agent.vel
+}
+
+/// flee steers agent away from target at full speed
+#[inline]
+pub fn flee(agent: &Agent, target: Vect) -> Vect {
+    target.to(agent.pos).norm() * agent.max_speed - agent.vel
+}
+
+/// arrive is seek that slows down inside slowing radius and stops at the target
+pub fn arrive(agent: &Agent, target: Vect, slowing: f32) -> Vect {
+    let to = agent.pos.to(target);
+    let dist = to.len();
+    if dist == 0f32 {
+        return agent.vel.inverted();
+    }
+    let speed = agent.max_speed * (dist / slowing).min(1f32);
+    to * (speed / dist) - agent.vel
+}
+
+/// pursue seeks position where target will be when agent gets there
+#[inline]
+pub fn pursue(agent: &Agent, target: &Agent) -> Vect {
+    seek(agent, predict(agent, target))
+}
+
+/// evade flees from position where target will be when it gets to agent
+#[inline]
+pub fn evade(agent: &Agent, target: &Agent) -> Vect {
+    flee(agent, predict(target, agent))
+}
+
+#[inline]
+fn predict(agent: &Agent, target: &Agent) -> Vect {
+    let time = agent.pos.dist(target.pos) / agent.max_speed.max(f32::EPSILON);
+    target.pos + target.vel * time
+}
+
+/// avoid steers agent sideways from the closest circle obstacle, given as (center, radius),
+/// that lies ahead. Distance agent looks ahead scales with its speed and is look_ahead at
+/// max speed.
+pub fn avoid(agent: &Agent, obstacles: &[(Vect, f32)], look_ahead: f32) -> Vect {
+    let speed = agent.vel.len();
+    if speed == 0f32 {
+        return Vect::ZERO;
+    }
+    let dir = agent.vel / speed;
+    let reach = look_ahead * speed / agent.max_speed;
+
+    let mut closest: Option<(f32, f32)> = None;
+    for &(center, radius) in obstacles.iter() {
+        let to = agent.pos.to(center);
+        let ahead = to.dot(dir);
+        let side = dir.cross(to);
+        if ahead < 0f32 || ahead > reach + radius || side.abs() >= radius + agent.radius {
+            continue;
+        }
+        if closest.is_none_or(|(a, _)| ahead < a) {
+            closest = Some((ahead, side));
+        }
+    }
+
+    match closest {
+        // obstacle on the left pushes to the right and the other way around
+        Some((ahead, side)) => {
+            let left = Vect::new(-dir.y, dir.x);
+            let strength = 1f32 - (ahead / (reach + f32::EPSILON)).min(1f32) * 0.5;
+            left * if side > 0f32 { -agent.max_force } else { agent.max_force } * strength
+        },
+        None => Vect::ZERO,
+    }
+}
+
+/// Wander makes agent roam randomly but smoothly by seeking point on circle in front of it
+/// that moves a little every call
+#[derive(Copy, Clone, Debug)]
+pub struct Wander {
+    /// distance of circle center from agent
+    pub distance: f32,
+    pub radius: f32,
+    /// jitter is max change of angle on the circle per call in radians
+    pub jitter: f32,
+    angle: f32,
+}
+
+impl Wander {
+    pub fn new(distance: f32, radius: f32, jitter: f32) -> Self {
+        Self { distance, radius, jitter, angle: 0f32 }
+    }
+
+    pub fn steer<R: Rng>(&mut self, agent: &Agent, rng: &mut R) -> Vect {
+        // empty range would panic, zero jitter just keeps the angle
+        let jitter = self.jitter.abs();
+        if jitter > 0f32 {
+            self.angle += rng.gen_range(-jitter, jitter);
+        }
+        let heading = if agent.vel == Vect::ZERO { Vect::RIGHT } else { agent.vel.norm() };
+        seek(agent, agent.pos + heading * self.distance + Vect::rad(heading.ang() + self.angle, self.radius))
+    }
+}
+
+/// PathFollower leads agent trough waypoints, point counts as reached once agent is closer
+/// then radius and agent arrives at the last one
+#[derive(Clone, Debug)]
+pub struct PathFollower {
+    path: Vec<Vect>,
+    current: usize,
+    pub radius: f32,
+    pub slowing: f32,
+}
+
+impl PathFollower {
+    pub fn new(path: Vec<Vect>, radius: f32, slowing: f32) -> Self {
+        Self { path, current: 0, radius, slowing }
+    }
+
+    /// set_path replaces path and starts from its beginning
+    pub fn set_path(&mut self, path: Vec<Vect>) {
+        self.path = path;
+        self.current = 0;
+    }
+
+    /// current returns waypoint agent is heading to
+    #[inline]
+    pub fn current(&self) -> Option<Vect> {
+        self.path.get(self.current).copied()
+    }
+
+    /// is_done returns whether agent reached the last waypoint
+    #[inline]
+    pub fn is_done(&self, agent: &Agent) -> bool {
+        self.path.last().is_none_or(|&p| self.current + 1 >= self.path.len() && agent.pos.dist(p) < self.radius)
+    }
+
+    pub fn steer(&mut self, agent: &Agent) -> Vect {
+        while self.current + 1 < self.path.len() && agent.pos.dist(self.path[self.current]) < self.radius {
+            self.current += 1;
+        }
+        match self.current() {
+            Some(point) if self.current + 1 == self.path.len() => arrive(agent, point, self.slowing),
+            Some(point) => seek(agent, point),
+            None => Vect::ZERO,
+        }
+    }
+}
+
+/// separation pushes agent away from neighbours closer then radius, the closer the stronger
+pub fn separation(agent: &Agent, neighbours: &[Agent], radius: f32) -> Vect {
+    let mut push = Vect::ZERO;
+    for other in neighbours.iter() {
+        let away = other.pos.to(agent.pos);
+        let dist = away.len();
+        if dist < radius && dist > 0f32 {
+            push += away / dist * (1f32 - dist / radius);
+        }
+    }
+    if push == Vect::ZERO {
+        return Vect::ZERO;
+    }
+    push.norm() * agent.max_speed - agent.vel
+}
+
+/// alignment steers agent to average heading of neighbours at full speed
+pub fn alignment(agent: &Agent, neighbours: &[Agent]) -> Vect {
+    if neighbours.is_empty() {
+        return Vect::ZERO;
+    }
+    let mut heading = Vect::ZERO;
+    for other in neighbours.iter() {
+        heading += other.vel;
+    }
+    heading.norm() * agent.max_speed - agent.vel
+}
+
+/// cohesion seeks center of neighbours
+pub fn cohesion(agent: &Agent, neighbours: &[Agent]) -> Vect {
+    if neighbours.is_empty() {
+        return Vect::ZERO;
+    }
+    let mut center = Vect::ZERO;
+    for other in neighbours.iter() {
+        center += other.pos;
+    }
+    seek(agent, center / neighbours.len() as f32)
+}
+
+/// Steering sums weighted forces of behaviours. Forces are added in order of priority and
+/// once max force of agent is used up the rest is ignored, so separation added first is not
+/// drowned by cohesion of big flock.
+#[derive(Copy, Clone, Debug)]
+pub struct Steering {
+    force: Vect,
+    budget: f32,
+}
+
+impl Steering {
+    pub fn new(agent: &Agent) -> Self {
+        Self { force: Vect::ZERO, budget: agent.max_force }
+    }
+
+    /// add adds weighted force, returns false if there is no force left for following ones
+    pub fn add(&mut self, force: Vect, weight: f32) -> bool {
+        if self.budget <= 0f32 {
+            return false;
+        }
+        let force = truncate(force * weight, self.budget);
+        self.budget -= force.len();
+        self.force += force;
+        self.budget > 0f32
+    }
+
+    #[inline]
+    pub fn force(&self) -> Vect {
+        self.force
+    }
+}
+
+/// Flocking holds weights of neighbourhood behaviours. Agents further then radius are not
+/// considered neighbours, separation reacts only to those closer then separation_radius.
+#[derive(Copy, Clone, Debug)]
+pub struct Flocking {
+    pub radius: f32,
+    pub separation_radius: f32,
+    pub separation: f32,
+    pub alignment: f32,
+    pub cohesion: f32,
+}
+
+impl Flocking {
+    /// steer adds separation, alignment and cohesion in this order
+    pub fn steer(&self, agent: &Agent, neighbours: &[Agent], steering: &mut Steering) {
+        if steering.add(separation(agent, neighbours, self.separation_radius), self.separation)
+            && steering.add(alignment(agent, neighbours), self.alignment) {
+            steering.add(cohesion(agent, neighbours), self.cohesion);
+        }
+    }
+}
+
+impl Default for Flocking {
+    fn default() -> Self {
+        Self { radius: 50f32, separation_radius: 20f32, separation: 1.5, alignment: 1f32, cohesion: 1f32 }
+    }
+}
+
+/// Flock owns agents and Scanner of their positions. Update computes forces of all agents in
+/// parallel on given count of threads, neighbours are looked up in the Scanner, then it moves
+/// agents and updates the Scanner. Agent is identified by its index, removing agent moves the
+/// last one to its index.
+pub struct Flock {
+    agents: Vec<Agent>,
+    forces: Vec<Vect>,
+    scanner: Scanner<usize>,
+    pub flocking: Flocking,
+    workers: usize,
+}
+
+impl Flock {
+    /// new creates empty flock, scanner should have tiles about the size of flocking radius
+    pub fn new(scanner: Scanner<usize>, flocking: Flocking, workers: usize) -> Self {
+        Self { agents: vec![], forces: vec![], scanner, flocking, workers: workers.max(1) }
+    }
+
+    /// add adds agent and returns its index
+    pub fn add(&mut self, agent: Agent) -> usize {
+        let index = self.agents.len();
+        self.scanner.insert(agent.pos, index);
+        self.agents.push(agent);
+        index
+    }
+
+    /// remove removes agent, the last agent takes its index
+    pub fn remove(&mut self, index: usize) -> Agent {
+        let last = self.agents.len() - 1;
+        self.scanner.remove(self.agents[index].pos, index);
+        if index != last {
+            self.scanner.remove(self.agents[last].pos, last);
+            self.scanner.insert(self.agents[last].pos, index);
+        }
+        self.agents.swap_remove(index)
+    }
+
+    #[inline]
+    pub fn agents(&self) -> &[Agent] {
+        &self.agents
+    }
+
+    #[inline]
+    pub fn scanner(&self) -> &Scanner<usize> {
+        &self.scanner
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+
+    /// update moves all agents by delta. Steer adds forces of other behaviours, it gets index
+    /// and agent and its forces have priority over flocking.
+    pub fn update<F>(&mut self, delta: f32, steer: F)
+        where F: Fn(usize, &Agent, &mut Steering) + Sync {
+        let Self { agents, forces, scanner, flocking, workers } = self;
+        forces.resize(agents.len(), Vect::ZERO);
+        if agents.is_empty() {
+            return;
+        }
+
+        let chunk = agents.len().div_ceil(clamp(*workers, 1, agents.len()));
+        let (view, cells, flocking, steer) = (&*agents, &*scanner, &*flocking, &steer);
+        thread::scope(|s| {
+            for (i, part) in forces.chunks_mut(chunk).enumerate() {
+                s.spawn(move || {
+                    let mut collector = vec![];
+                    let mut neighbours = vec![];
+                    for (j, force) in part.iter_mut().enumerate() {
+                        let index = i * chunk + j;
+                        let agent = &view[index];
+                        collector.clear();
+                        neighbours.clear();
+                        cells.query(&Rect::cube(agent.pos, flocking.radius), &mut collector);
+                        neighbours.extend(collector.iter()
+                            .filter(|&&o| o != index)
+                            .map(|&o| view[o])
+                            .filter(|o| o.pos.dist(agent.pos) < flocking.radius));
+
+                        let mut steering = Steering::new(agent);
+                        steer(index, agent, &mut steering);
+                        flocking.steer(agent, &neighbours, &mut steering);
+                        *force = steering.force();
+                    }
+                });
+            }
+        });
+
+        for (index, (agent, &force)) in agents.iter_mut().zip(forces.iter()).enumerate() {
+            let old = agent.pos;
+            agent.apply(force, delta);
+            scanner.update(old, agent.pos, index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::steering::{Agent, Flock, Flocking, PathFollower, Wander, arrive, avoid, seek};
+    use crate::entity::scanner::Scanner;
+    use crate::math::vect::Vect;
+
+    #[test]
+    fn behaviours_test() {
+        let mut agent = Agent::new(Vect::ZERO, 1f32, 10f32, 20f32);
+        for _ in 0..200 {
+            let force = arrive(&agent, Vect::new(50f32, 0f32), 20f32);
+            agent.apply(force, 0.05);
+        }
+        assert!(agent.pos.dist(Vect::new(50f32, 0f32)) < 1f32);
+        assert!(agent.vel.len() < 1f32);
+
+        agent.vel = Vect::new(10f32, 0f32);
+        let obstacle = [(Vect::new(60f32, 1f32), 3f32)];
+        assert!(avoid(&agent, &obstacle, 20f32).y < 0f32);
+        assert_eq!(Vect::ZERO, avoid(&agent, &obstacle, 5f32));
+        assert!(seek(&agent, Vect::new(40f32, 0f32)).x < 0f32);
+
+        let mut wander = Wander::new(10f32, 5f32, 0f32);
+        assert!(wander.steer(&agent, &mut rand::thread_rng()).y.abs() < 0.001);
+
+        let mut follower = PathFollower::new(vec![Vect::new(50f32, 20f32), Vect::new(0f32, 20f32)], 2f32, 10f32);
+        for _ in 0..400 {
+            let force = follower.steer(&agent);
+            agent.apply(force, 0.05);
+        }
+        assert!(follower.is_done(&agent));
+    }
+
+    #[test]
+    fn flock_test() {
+        let flocking = Flocking { radius: 10f32, separation_radius: 3f32, cohesion: 0.1, ..Flocking::default() };
+        let mut flock = Flock::new(Scanner::new(10, 10, Vect::mirror(10f32)), flocking, 4);
+        for i in 0..20 {
+            let mut agent = Agent::new(Vect::new(40f32 + (i % 5 * 4) as f32, 40f32 + (i / 5 * 4) as f32), 1f32, 5f32, 10f32);
+            agent.vel = Vect::rad((i % 7) as f32 * 0.3 - 0.9, 5f32);
+            flock.add(agent);
+        }
+
+        for _ in 0..100 {
+            flock.update(0.05, |_, _, _| {});
+        }
+
+        let agents = flock.agents();
+        let closest = agents.iter().enumerate()
+            .flat_map(|(i, a)| agents[i + 1..].iter().map(move |b| a.pos.dist(b.pos)))
+            .fold(f32::MAX, f32::min);
+        assert!(closest > 2f32);
+
+        // velocities got aligned
+        let heading = agents[0].vel.norm();
+        assert!(agents.iter().all(|a| a.vel.norm().dot(heading) > 0.95));
+
+        let removed = flock.remove(3);
+        let mut collector = vec![];
+        flock.scanner().query_point(flock.agents()[3].pos, &mut collector);
+        assert!(collector.contains(&3) && !collector.contains(&19));
+        assert_eq!(19, flock.len());
+        assert!(removed.pos.dist(flock.agents()[3].pos) > 0f32);
+    }
+}
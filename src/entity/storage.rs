@@ -1,8 +1,29 @@
+/// Entity is handle to component in ComponentArray. Slot of removed component gets reused but
+/// its generation is increased, so handles to removed components never address new ones.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entity {
+    pub index: usize,
+    pub generation: u32,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+    /// taken is whether value is temporarily out, see `ComponentArray::take`
+    taken: bool,
+}
+
+/// ComponentArray stores components in dense vector of slots, free slots are recycled. With
+/// `serde` feature it serializes together with generations and free slots, so restored array
+/// hands out the same handles as the original would.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Raw<T>"))]
 pub struct ComponentArray<T> {
-    inner: Vec<Option<T>>,
-    ids: Vec<usize>,
+    inner: Vec<Slot<T>>,
+    free: Vec<usize>,
     len: usize,
-    id: usize,
 }
 
 impl<T> ComponentArray<T> {
@@ -10,42 +31,228 @@ impl<T> ComponentArray<T> {
     pub fn new() -> Self {
         Self {
             inner: vec![],
-            ids: vec![],
+            free: vec![],
             len: 0,
-            id: 0,
         }
     }
 
+    /// insert stores component and returns its handle
+    pub fn insert(&mut self, component: T) -> Entity {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.inner[index];
+            slot.value = Some(component);
+            return Entity { index, generation: slot.generation };
+        }
+
+        self.inner.push(Slot { generation: 0, value: Some(component), taken: false });
+        Entity { index: self.inner.len() - 1, generation: 0 }
+    }
+
+    /// remove removes component and frees its slot, None if entity is stale
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let slot = self.slot_mut(entity)?;
+        if slot.value.is_none() && !slot.taken {
+            return None;
+        }
+        let value = slot.value.take();
+        slot.taken = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(entity.index);
+        self.len -= 1;
+        value
+    }
+
+    /// contains returns whether entity is alive
+    #[inline]
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.slot(entity).is_some_and(|s| s.value.is_some() || s.taken)
+    }
+
     #[inline]
-    pub fn insert(&mut self, component: T) -> usize {
-        self.len = self.inner.len() - 1;
-        if self.ids.is_empty() {
-            self.inner.push(Some(component));
-            return self.len
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.slot(entity)?.value.as_ref()
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.slot_mut(entity)?.value.as_mut()
+    }
+
+    /// take moves component out while keeping entity alive so component can be modified
+    /// without borrowing the array, it has to be returned with `put_back`
+    #[inline]
+    pub fn take(&mut self, entity: Entity) -> Option<T> {
+        let slot = self.slot_mut(entity)?;
+        let value = slot.value.take();
+        slot.taken |= value.is_some();
+        value
+    }
+
+    /// put_back returns taken component, it returns false if entity is stale
+    pub fn put_back(&mut self, entity: Entity, component: T) -> bool {
+        match self.slot_mut(entity) {
+            Some(slot) if slot.taken => {
+                slot.value = Some(component);
+                slot.taken = false;
+                true
+            },
+            _ => false,
         }
-        self.id = self.ids[self.len];
-        self.inner[self.id] = Some(component);
-        self.ids.truncate(self.len);
+    }
 
-        self.id
+    /// len returns count of alive entities
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
     }
 
     #[inline]
-    pub fn get(&mut self, id: usize) -> Option<T> {
-        std::mem::replace(&mut self.inner[id], None)
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// slot_count returns count of slots including free ones, every entity index is below it
+    #[inline]
+    pub fn slot_count(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// entity returns alive entity occupying slot of given index
+    #[inline]
+    pub fn entity(&self, index: usize) -> Option<Entity> {
+        let slot = self.inner.get(index)?;
+        if slot.value.is_some() || slot.taken {
+            Some(Entity { index, generation: slot.generation })
+        } else {
+            None
+        }
     }
 
-    pub fn put_back(&mut self, id: usize, component: T) {
-        self.inner[id] = Some(component);
+    /// iter iterates over entities with their components, taken components are skipped
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.inner.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value.as_ref().map(|v| (Entity { index, generation: slot.generation }, v))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.inner.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let generation = slot.generation;
+            slot.value.as_mut().map(|v| (Entity { index, generation }, v))
+        })
     }
 
     #[inline]
-    pub fn get_fer(&self, id: usize) -> &Option<T> {
-        &self.inner[id]
+    fn slot(&self, entity: Entity) -> Option<&Slot<T>> {
+        self.inner.get(entity.index).filter(|s| s.generation == entity.generation)
     }
 
     #[inline]
-    pub fn get_mut(&mut self, id: usize) -> &mut Option<T> {
-        &mut self.inner[id]
+    fn slot_mut(&mut self, entity: Entity) -> Option<&mut Slot<T>> {
+        self.inner.get_mut(entity.index).filter(|s| s.generation == entity.generation)
+    }
+}
+
+/// Raw is ComponentArray as it is deserialized, before free slots and len are checked
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct Raw<T> {
+    inner: Vec<Slot<T>>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+#[cfg(feature = "serde")]
+impl<T> std::convert::TryFrom<Raw<T>> for ComponentArray<T> {
+    type Error = String;
+
+    fn try_from(raw: Raw<T>) -> Result<Self, Self::Error> {
+        let mut listed = vec![false; raw.inner.len()];
+        for &index in raw.free.iter() {
+            match raw.inner.get(index) {
+                Some(slot) if !listed[index] && slot.value.is_none() && !slot.taken => listed[index] = true,
+                _ => return Err(format!("slot {} cannot be free", index)),
+            }
+        }
+
+        let mut len = 0;
+        for (index, slot) in raw.inner.iter().enumerate() {
+            if slot.value.is_some() && slot.taken {
+                return Err(format!("slot {} is both taken and occupied", index));
+            }
+            if slot.value.is_some() || slot.taken {
+                len += 1;
+            } else if !listed[index] {
+                return Err(format!("empty slot {} is not free", index));
+            }
+        }
+        if len != raw.len {
+            return Err(format!("array with {} alive entities cannot have len {}", len, raw.len));
+        }
+
+        Ok(Self { inner: raw.inner, free: raw.free, len })
+    }
+}
+
+impl<T> Default for ComponentArray<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::storage::ComponentArray;
+
+    #[test]
+    fn insert_test() {
+        let mut array = ComponentArray::new();
+        let a = array.insert("a");
+        let b = array.insert("b");
+        assert_eq!(Some(&"a"), array.get(a));
+        assert_eq!(2, array.len());
+
+        assert_eq!(Some("a"), array.remove(a));
+        assert_eq!(None, array.remove(a));
+        let c = array.insert("c");
+        assert_eq!(a.index, c.index);
+        assert_eq!(None, array.get(a));
+        assert_eq!(Some(&"c"), array.get(c));
+
+        let taken = array.take(b).unwrap();
+        assert!(array.contains(b) && array.get(b).is_none());
+        assert!(array.put_back(b, taken));
+        assert_eq!(vec![c, b], array.iter().map(|(e, _)| e).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_test() {
+        use crate::entity::storage::Entity;
+
+        let mut array = ComponentArray::new();
+        let entities: Vec<_> = (0..10).map(|i| array.insert(i)).collect();
+        for e in entities.iter().step_by(3) {
+            array.remove(*e);
+        }
+
+        let binary = bincode::serialize(&array).unwrap();
+        let text = serde_json::to_string(&array).unwrap();
+        for mut restored in [
+            bincode::deserialize::<ComponentArray<i32>>(&binary).unwrap(),
+            serde_json::from_str::<ComponentArray<i32>>(&text).unwrap(),
+        ] {
+            assert_eq!(array.iter().collect::<Vec<_>>(), restored.iter().collect::<Vec<_>>());
+            assert_eq!(None, restored.get(entities[9]));
+            assert_eq!(Entity { index: 9, generation: 1 }, restored.insert(100));
+            assert_eq!(7, restored.len());
+        }
+
+        let broken = text.replacen("\"len\":6", "\"len\":7", 1);
+        assert!(broken != text && serde_json::from_str::<ComponentArray<i32>>(&broken).is_err());
+        let broken = text.replacen("\"free\":[", "\"free\":[42,", 1);
+        assert!(serde_json::from_str::<ComponentArray<i32>>(&broken).is_err());
     }
 }
@@ -0,0 +1,484 @@
+use crate::entity::storage::{ComponentArray, Entity};
+use crate::entity::{FastHash, gen_hash};
+use std::any::{Any, TypeId, type_name};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Component is any type that can be stored in World
+pub trait Component: Send + Sync + 'static {}
+
+impl<T: Send + Sync + 'static> Component for T {}
+
+/// Column stores components of one type indexed by entity index
+pub struct Column<T> {
+    data: Vec<Option<T>>,
+}
+
+trait AnyColumn: Send + Sync {
+    fn remove(&mut self, index: usize);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Component> AnyColumn for RwLock<Column<T>> {
+    fn remove(&mut self, index: usize) {
+        if let Some(slot) = self.get_mut().unwrap().data.get_mut(index) {
+            *slot = None;
+        }
+    }
+
+    #[inline]
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// World stores entities and their components by type together with resources, values
+/// that exist once per world. Entities are generational handles from ComponentArray so
+/// handle of despawned entity never addresses a new one. Entity is cheap to copy and
+/// hashable, so it can be stored in Scanner for spatial queries.
+///
+/// Columns and resources are behind locks so queries need only `&World` and systems can run
+/// in parallel, borrowing the same column mutably twice at once panics.
+pub struct World {
+    entities: ComponentArray<()>,
+    columns: HashMap<TypeId, Box<dyn AnyColumn>, FastHash>,
+    resources: HashMap<TypeId, RwLock<Box<dyn Any + Send + Sync>>, FastHash>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self {
+            entities: ComponentArray::new(),
+            columns: HashMap::with_hasher(gen_hash()),
+            resources: HashMap::with_hasher(gen_hash()),
+        }
+    }
+
+    /// spawn creates entity with components of bundle, for example `world.spawn((pos, vel))`
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity {
+        let entity = self.entities.insert(());
+        bundle.insert(self, entity);
+        entity
+    }
+
+    /// despawn removes entity with all its components, false if it is already dead
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if self.entities.remove(entity).is_none() {
+            return false;
+        }
+        for column in self.columns.values_mut() {
+            column.remove(entity.index);
+        }
+        true
+    }
+
+    #[inline]
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.entities.contains(entity)
+    }
+
+    /// len returns count of alive entities
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// insert adds component to entity or replaces the old one, false if entity is dead
+    pub fn insert<T: Component>(&mut self, entity: Entity, component: T) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        let data = &mut self.column_mut::<T>().data;
+        if data.len() <= entity.index {
+            data.resize_with(entity.index + 1, || None);
+        }
+        data[entity.index] = Some(component);
+        true
+    }
+
+    /// remove removes component from entity and returns it
+    pub fn remove<T: Component>(&mut self, entity: Entity) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.column_mut::<T>().data.get_mut(entity.index)?.take()
+    }
+
+    /// has returns whether entity has component of given type
+    pub fn has<T: Component>(&self, entity: Entity) -> bool {
+        self.is_alive(entity) && read_column::<T>(self).is_some_and(|c| c.data.get(entity.index).is_some_and(|c| c.is_some()))
+    }
+
+    /// get_mut returns component of entity, reading components through shared world is done
+    /// with `query`
+    pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.column_mut::<T>().data.get_mut(entity.index)?.as_mut()
+    }
+
+    /// query locks columns needed by Q, for example `world.query::<(&mut Pos, &Vel)>()`, and
+    /// returns guard that iterates over entities having all of them
+    pub fn query<Q: Query>(&self) -> QueryGuard<'_, Q> {
+        QueryGuard { guard: Q::lock(self), entities: &self.entities }
+    }
+
+    /// insert_resource stores resource, old resource of the same type is replaced
+    pub fn insert_resource<R: Component>(&mut self, resource: R) {
+        self.resources.insert(TypeId::of::<R>(), RwLock::new(Box::new(resource)));
+    }
+
+    pub fn remove_resource<R: Component>(&mut self) -> Option<R> {
+        let resource = self.resources.remove(&TypeId::of::<R>())?;
+        resource.into_inner().unwrap().downcast().ok().map(|r| *r)
+    }
+
+    /// resource returns shared borrow of resource
+    pub fn resource<R: Component>(&self) -> Option<Res<'_, R>> {
+        let lock = self.resources.get(&TypeId::of::<R>())?;
+        let guard = lock.try_read().unwrap_or_else(|_| panic!("resource {} is already borrowed mutably", type_name::<R>()));
+        Some(Res { guard, marker: PhantomData })
+    }
+
+    /// resource_mut returns exclusive borrow of resource
+    pub fn resource_mut<R: Component>(&self) -> Option<ResMut<'_, R>> {
+        let lock = self.resources.get(&TypeId::of::<R>())?;
+        let guard = lock.try_write().unwrap_or_else(|_| panic!("resource {} is already borrowed", type_name::<R>()));
+        Some(ResMut { guard, marker: PhantomData })
+    }
+
+    fn column_mut<T: Component>(&mut self) -> &mut Column<T> {
+        self.columns.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(RwLock::new(Column::<T> { data: vec![] })))
+            .as_any_mut()
+            .downcast_mut::<RwLock<Column<T>>>()
+            .unwrap()
+            .get_mut()
+            .unwrap()
+    }
+
+    #[inline]
+    fn column<T: Component>(&self) -> Option<&RwLock<Column<T>>> {
+        self.columns.get(&TypeId::of::<T>())?.as_any().downcast_ref()
+    }
+}
+
+impl Default for World {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[inline]
+fn read_column<T: Component>(world: &World) -> Option<RwLockReadGuard<'_, Column<T>>> {
+    let column = world.column::<T>()?;
+    Some(column.try_read().unwrap_or_else(|_| panic!("component {} is already borrowed mutably", type_name::<T>())))
+}
+
+/// Bundle is group of components inserted together, it is implemented for tuples
+pub trait Bundle {
+    fn insert(self, world: &mut World, entity: Entity);
+}
+
+/// Access is set of component and resource types something reads and writes
+#[derive(Clone, Debug, Default)]
+pub struct Access {
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
+}
+
+impl Access {
+    #[inline]
+    pub fn read<T: 'static>(&mut self) {
+        self.reads.push(TypeId::of::<T>());
+    }
+
+    #[inline]
+    pub fn write<T: 'static>(&mut self) {
+        self.writes.push(TypeId::of::<T>());
+    }
+
+    /// extend adds everything other accesses
+    pub fn extend(&mut self, other: &Access) {
+        self.reads.extend(&other.reads);
+        self.writes.extend(&other.writes);
+    }
+
+    /// conflicts returns whether one of accesses writes something the other uses
+    pub fn conflicts(&self, other: &Access) -> bool {
+        self.writes.iter().any(|w| other.writes.contains(w) || other.reads.contains(w)) ||
+            other.writes.iter().any(|w| self.reads.contains(w))
+    }
+}
+
+/// Query describes components fetched together, it is implemented for `&T`, `&mut T` and
+/// tuples of them
+pub trait Query {
+    type Guard<'w>;
+    type Item<'a>;
+
+    /// lock borrows columns of the query
+    fn lock(world: &World) -> Self::Guard<'_>;
+
+    /// fetch returns components of entity with given index if it has all of them
+    ///
+    /// # Safety
+    ///
+    /// Items of the same index must not be alive twice at once and they must not outlive the
+    /// guard.
+    unsafe fn fetch<'a>(guard: &Self::Guard<'_>, index: usize) -> Option<Self::Item<'a>>;
+
+    /// access adds types the query uses to access
+    fn access(access: &mut Access);
+}
+
+impl<T: Component> Query for &T {
+    type Guard<'w> = Option<RwLockReadGuard<'w, Column<T>>>;
+    type Item<'a> = &'a T;
+
+    #[inline]
+    fn lock(world: &World) -> Self::Guard<'_> {
+        read_column::<T>(world)
+    }
+
+    #[inline]
+    unsafe fn fetch<'a>(guard: &Self::Guard<'_>, index: usize) -> Option<Self::Item<'a>> {
+        let component = guard.as_ref()?.data.get(index)?.as_ref()?;
+        Some(&*(component as *const T))
+    }
+
+    #[inline]
+    fn access(access: &mut Access) {
+        access.read::<T>();
+    }
+}
+
+/// WriteGuard keeps column locked and points to its data so components can be fetched
+/// mutably trough shared guard
+pub struct WriteGuard<'w, T> {
+    _guard: RwLockWriteGuard<'w, Column<T>>,
+    data: *mut Option<T>,
+    len: usize,
+}
+
+impl<T: Component> Query for &mut T {
+    type Guard<'w> = Option<WriteGuard<'w, T>>;
+    type Item<'a> = &'a mut T;
+
+    fn lock(world: &World) -> Self::Guard<'_> {
+        let column = world.column::<T>()?;
+        let mut guard = column.try_write().unwrap_or_else(|_| panic!("component {} is already borrowed", type_name::<T>()));
+        let (data, len) = (guard.data.as_mut_ptr(), guard.data.len());
+        Some(WriteGuard { _guard: guard, data, len })
+    }
+
+    #[inline]
+    unsafe fn fetch<'a>(guard: &Self::Guard<'_>, index: usize) -> Option<Self::Item<'a>> {
+        let guard = guard.as_ref()?;
+        if index >= guard.len {
+            return None;
+        }
+        (*guard.data.add(index)).as_mut()
+    }
+
+    #[inline]
+    fn access(access: &mut Access) {
+        access.write::<T>();
+    }
+}
+
+macro_rules! tuple_impls {
+    ($($t:ident),*) => {
+        impl<$($t: Component),*> Bundle for ($($t,)*) {
+            #[allow(non_snake_case, unused_variables)]
+            fn insert(self, world: &mut World, entity: Entity) {
+                let ($($t,)*) = self;
+                $(world.insert(entity, $t);)*
+            }
+        }
+
+        impl<$($t: Query),*> Query for ($($t,)*) {
+            type Guard<'w> = ($($t::Guard<'w>,)*);
+            type Item<'a> = ($($t::Item<'a>,)*);
+
+            #[allow(unused_variables, clippy::unused_unit)]
+            fn lock(world: &World) -> Self::Guard<'_> {
+                ($($t::lock(world),)*)
+            }
+
+            #[allow(non_snake_case, unused_variables, clippy::unused_unit)]
+            unsafe fn fetch<'a>(guard: &Self::Guard<'_>, index: usize) -> Option<Self::Item<'a>> {
+                let ($($t,)*) = guard;
+                Some(($($t::fetch($t, index)?,)*))
+            }
+
+            #[allow(unused_variables)]
+            fn access(access: &mut Access) {
+                $($t::access(access);)*
+            }
+        }
+    };
+}
+
+tuple_impls!();
+tuple_impls!(A);
+tuple_impls!(A, B);
+tuple_impls!(A, B, C);
+tuple_impls!(A, B, C, D);
+tuple_impls!(A, B, C, D, E);
+tuple_impls!(A, B, C, D, E, F);
+tuple_impls!(A, B, C, D, E, F, G);
+tuple_impls!(A, B, C, D, E, F, G, H);
+
+/// QueryGuard holds columns of query locked, items borrow the guard mutably so two
+/// iterations cannot overlap
+pub struct QueryGuard<'w, Q: Query> {
+    guard: Q::Guard<'w>,
+    entities: &'w ComponentArray<()>,
+}
+
+impl<'w, Q: Query> QueryGuard<'w, Q> {
+    /// iter iterates over entities that have all components of query
+    #[inline]
+    pub fn iter(&mut self) -> QueryIter<'_, 'w, Q> {
+        QueryIter { guard: &self.guard, entities: self.entities, index: 0 }
+    }
+
+    /// get returns components of entity, None if it is dead or misses some of them
+    pub fn get(&mut self, entity: Entity) -> Option<Q::Item<'_>> {
+        if !self.entities.contains(entity) {
+            return None;
+        }
+        // guard is borrowed mutably so only this item is alive
+        unsafe { Q::fetch(&self.guard, entity.index) }
+    }
+}
+
+pub struct QueryIter<'a, 'w, Q: Query> {
+    guard: &'a Q::Guard<'w>,
+    entities: &'a ComponentArray<()>,
+    index: usize,
+}
+
+impl<'a, Q: Query> Iterator for QueryIter<'a, '_, Q> {
+    type Item = (Entity, Q::Item<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.entities.slot_count() {
+            let index = self.index;
+            self.index += 1;
+            if let Some(entity) = self.entities.entity(index) {
+                // every index is visited once and iterator borrows the guard
+                if let Some(item) = unsafe { Q::fetch(self.guard, index) } {
+                    return Some((entity, item));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Res is shared borrow of resource
+pub struct Res<'a, R> {
+    guard: RwLockReadGuard<'a, Box<dyn Any + Send + Sync>>,
+    marker: PhantomData<R>,
+}
+
+impl<R: 'static> Deref for Res<'_, R> {
+    type Target = R;
+
+    #[inline]
+    fn deref(&self) -> &R {
+        self.guard.downcast_ref().unwrap()
+    }
+}
+
+/// ResMut is exclusive borrow of resource
+pub struct ResMut<'a, R> {
+    guard: RwLockWriteGuard<'a, Box<dyn Any + Send + Sync>>,
+    marker: PhantomData<R>,
+}
+
+impl<R: 'static> Deref for ResMut<'_, R> {
+    type Target = R;
+
+    #[inline]
+    fn deref(&self) -> &R {
+        self.guard.downcast_ref().unwrap()
+    }
+}
+
+impl<R: 'static> DerefMut for ResMut<'_, R> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut R {
+        self.guard.downcast_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::world::World;
+    use crate::entity::scanner::Scanner;
+    use crate::math::vect::Vect;
+
+    struct Pos(Vect);
+    struct Vel(Vect);
+    struct Frame(usize);
+
+    #[test]
+    fn query_test() {
+        let mut world = World::new();
+        let a = world.spawn((Pos(Vect::ZERO), Vel(Vect::new(1f32, 0f32))));
+        let b = world.spawn((Pos(Vect::new(50f32, 50f32)),));
+        let c = world.spawn((Pos(Vect::ZERO), Vel(Vect::new(0f32, 2f32))));
+        world.insert_resource(Frame(0));
+
+        for _ in 0..3 {
+            for (_, (pos, vel)) in world.query::<(&mut Pos, &Vel)>().iter() {
+                pos.0 += vel.0;
+            }
+            world.resource_mut::<Frame>().unwrap().0 += 1;
+        }
+        assert_eq!(3, world.resource::<Frame>().unwrap().0);
+        assert_eq!(Vect::new(3f32, 0f32), world.query::<&Pos>().get(a).unwrap().0);
+        assert_eq!(2, world.query::<&Vel>().iter().count());
+
+        assert!(world.despawn(c));
+        assert!(!world.despawn(c));
+        let d = world.spawn((Vel(Vect::ZERO),));
+        assert_eq!(c.index, d.index);
+        assert!(world.query::<&Pos>().get(c).is_none());
+        assert!(!world.has::<Pos>(d) && world.has::<Vel>(d));
+
+        let mut scanner = Scanner::new(10, 10, Vect::new(10f32, 10f32));
+        for (entity, pos) in world.query::<&Pos>().iter() {
+            scanner.insert(pos.0, entity);
+        }
+        assert!(scanner.map[(5, 5)].contains(&b));
+        assert_eq!(Some(Frame(3)).map(|f| f.0), world.remove_resource::<Frame>().map(|f| f.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn conflict_test() {
+        let mut world = World::new();
+        world.spawn((Pos(Vect::ZERO),));
+        let _a = world.query::<&mut Pos>();
+        let _b = world.query::<&Pos>();
+    }
+}
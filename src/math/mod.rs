@@ -3,6 +3,7 @@ pub mod base;
 pub mod rgba;
 pub mod mat;
 pub mod rect;
+pub mod sweep;
 pub mod vect;
 
 #[inline]
@@ -10,6 +10,7 @@ pub struct Rect {
     pub max: Vect,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Sides {
     Right, Left, Top, Bottom, In
 }
@@ -217,11 +218,59 @@ impl Rect {
     pub fn centered_to(&self, pos: Vect) -> Self {
         Self::centered(pos, self.width(), self.height())
     }
+
+    /// expanded returns rect grown by margin on every side
+    #[inline]
+    pub fn expanded(&self, margin: f32) -> Self {
+        Rect {
+            min: Vect { x: self.min.x - margin, y: self.min.y - margin },
+            max: Vect { x: self.max.x + margin, y: self.max.y + margin },
+        }
+    }
+
+    /// perimeter returns sum of rect sides
+    #[inline]
+    pub fn perimeter(&self) -> f32 {
+        (self.width() + self.height()) * 2f32
+    }
+
+    /// ray_cast returns fraction of segment from `from` to `to` where segment enters the rect,
+    /// 0 if from is already inside and None if segment misses the rect
+    pub fn ray_cast(&self, from: Vect, to: Vect) -> Option<f32> {
+        let d = to - from;
+        let mut t_min = 0f32;
+        let mut t_max = 1f32;
+
+        for (o, d, min, max) in [(from.x, d.x, self.min.x, self.max.x), (from.y, d.y, self.min.y, self.max.y)].iter() {
+            if d.abs() < f32::EPSILON {
+                if o < min || o > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let inv = 1f32 / d;
+            let mut t1 = (min - o) * inv;
+            let mut t2 = (max - o) * inv;
+            if t1 > t2 {
+                mem::swap(&mut t1, &mut t2);
+            }
+
+            t_min = t_min.max(t1);
+            t_max = t_max.min(t2);
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::math::rect::Rect;
+    use crate::math::vect::Vect;
 
     #[test]
     fn intersects_test() {
@@ -230,4 +279,13 @@ mod tests {
         assert!(base.intersects(&Rect::new(10f32, 10f32, 100f32, 100f32)));
         assert!(!base.intersects(&Rect::new(100f32, 100f32, 1000f32, 1000f32)));
     }
+
+    #[test]
+    fn ray_cast_test() {
+        let base = Rect::new(0f32, 0f32, 10f32, 10f32);
+        assert_eq!(Some(0.5), base.ray_cast(Vect::new(-10f32, 5f32), Vect::new(10f32, 5f32)));
+        assert_eq!(Some(0f32), base.ray_cast(Vect::new(5f32, 5f32), Vect::new(20f32, 5f32)));
+        assert_eq!(None, base.ray_cast(Vect::new(-10f32, 5f32), Vect::new(-1f32, 5f32)));
+        assert_eq!(None, base.ray_cast(Vect::new(-10f32, 20f32), Vect::new(20f32, 20f32)));
+    }
 }
\ No newline at end of file
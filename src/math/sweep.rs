@@ -0,0 +1,177 @@
+use crate::math::rect::Rect;
+use crate::math::vect::Vect;
+
+/// Hit is result of continuous collision test. Time is fraction of the motion at witch shapes
+/// touch and normal points from obstacle to the moving shape.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub time: f32,
+    pub normal: Vect,
+}
+
+/// swept_rect returns first contact of rect a moving by delta with static rect b. If rects
+/// already overlap, time is 0 and normal points the shortest way out.
+pub fn swept_rect(a: &Rect, delta: Vect, b: &Rect) -> Option<Hit> {
+    let half = (a.max - a.min) / 2f32;
+    let target = Rect { min: b.min - half, max: b.max + half };
+    let origin = a.center();
+
+    if target.contains(origin) {
+        let left = origin.x - target.min.x;
+        let right = target.max.x - origin.x;
+        let bottom = origin.y - target.min.y;
+        let top = target.max.y - origin.y;
+        let min = left.min(right).min(bottom).min(top);
+        let normal = if min == left {
+            Vect::LEFT
+        } else if min == right {
+            Vect::RIGHT
+        } else if min == bottom {
+            Vect::DOWN
+        } else {
+            Vect::UP
+        };
+        return Some(Hit { time: 0f32, normal });
+    }
+
+    let (x_near, x_far) = slab(origin.x, delta.x, target.min.x, target.max.x)?;
+    let (y_near, y_far) = slab(origin.y, delta.y, target.min.y, target.max.y)?;
+
+    let near = x_near.max(y_near);
+    let far = x_far.min(y_far);
+    if near > far || !(0f32..=1f32).contains(&near) {
+        return None;
+    }
+
+    let normal = if x_near > y_near {
+        Vect::new(-delta.x.signum(), 0f32)
+    } else {
+        Vect::new(0f32, -delta.y.signum())
+    };
+
+    Some(Hit { time: near, normal })
+}
+
+/// swept_circle_segment returns first contact of circle moving by delta with segment from a to b
+pub fn swept_circle_segment(center: Vect, radius: f32, delta: Vect, a: Vect, b: Vect) -> Option<Hit> {
+    let closest = closest_on_segment(center, a, b);
+    let d = center - closest;
+    if d.len() < radius {
+        let normal = if d == Vect::ZERO {delta.inverted().norm()} else {d.norm()};
+        return Some(Hit { time: 0f32, normal });
+    }
+
+    let mut best: Option<Hit> = None;
+
+    let edge = b - a;
+    let mut n = Vect::new(-edge.y, edge.x).norm();
+    let mut dist = (center - a).dot(n);
+    if dist < 0f32 {
+        n = n.inverted();
+        dist = -dist;
+    }
+    let speed = delta.dot(n);
+    if speed < 0f32 {
+        let t = (dist - radius) / -speed;
+        let contact = center + delta * t - n * radius;
+        let l = edge.dot(edge);
+        let along = if l == 0f32 {-1f32} else {(contact - a).dot(edge) / l};
+        if (0f32..=1f32).contains(&t) && (0f32..=1f32).contains(&along) {
+            best = Some(Hit { time: t, normal: n });
+        }
+    }
+
+    for p in [a, b].iter() {
+        if let Some(t) = ray_circle(center, delta, *p, radius) {
+            if best.is_none_or(|h| t < h.time) {
+                best = Some(Hit { time: t, normal: (center + delta * t - *p).norm() });
+            }
+        }
+    }
+
+    best
+}
+
+/// ray_circle returns fraction of segment from origin to origin + delta where it enters circle
+pub fn ray_circle(origin: Vect, delta: Vect, center: Vect, radius: f32) -> Option<f32> {
+    let f = origin - center;
+    let a = delta.dot(delta);
+    if a == 0f32 {
+        return None;
+    }
+    let b = 2f32 * f.dot(delta);
+    let c = f.dot(f) - radius * radius;
+    let discriminant = b * b - 4f32 * a * c;
+    if discriminant < 0f32 {
+        return None;
+    }
+
+    let t = (-b - discriminant.sqrt()) / (2f32 * a);
+    if (0f32..=1f32).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// closest_on_segment returns point on segment from a to b closest to p
+#[inline]
+pub fn closest_on_segment(p: Vect, a: Vect, b: Vect) -> Vect {
+    let ab = b - a;
+    let l = ab.dot(ab);
+    if l == 0f32 {
+        return a;
+    }
+    a + ab * super::clamp_f((p - a).dot(ab) / l, 0f32, 1f32)
+}
+
+/// slab returns times when ray enters and leaves space between min and max
+#[inline]
+fn slab(origin: f32, delta: f32, min: f32, max: f32) -> Option<(f32, f32)> {
+    if delta == 0f32 {
+        return if origin < min || origin > max {
+            None
+        } else {
+            Some((f32::NEG_INFINITY, f32::INFINITY))
+        };
+    }
+
+    let t1 = (min - origin) / delta;
+    let t2 = (max - origin) / delta;
+    Some((t1.min(t2), t1.max(t2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::sweep::{swept_rect, swept_circle_segment};
+    use crate::math::rect::Rect;
+    use crate::math::vect::Vect;
+
+    #[test]
+    fn swept_rect_test() {
+        let wall = Rect::new(100f32, -100f32, 101f32, 100f32);
+        let bullet = Rect::cube(Vect::ZERO, 1f32);
+        let hit = swept_rect(&bullet, Vect::new(1000f32, 0f32), &wall).unwrap();
+        assert!((hit.time - 0.099).abs() < 0.0001);
+        assert_eq!(Vect::LEFT, hit.normal);
+        assert_eq!(None, swept_rect(&bullet, Vect::new(1000f32, 3000f32), &wall));
+        assert_eq!(None, swept_rect(&bullet, Vect::new(50f32, 0f32), &wall));
+    }
+
+    #[test]
+    fn swept_circle_segment_test() {
+        let a = Vect::new(100f32, -100f32);
+        let b = Vect::new(100f32, 100f32);
+        let hit = swept_circle_segment(Vect::ZERO, 10f32, Vect::new(1000f32, 0f32), a, b).unwrap();
+        assert!((hit.time - 0.09).abs() < 0.0001);
+        assert_eq!(Vect::LEFT, hit.normal);
+
+        let hit = swept_circle_segment(Vect::new(0f32, 105f32), 10f32, Vect::new(1000f32, 0f32), a, b).unwrap();
+        assert!(hit.time > 0.09 && hit.time < 0.1);
+        assert!(swept_circle_segment(Vect::new(0f32, 200f32), 10f32, Vect::new(1000f32, 0f32), a, b).is_none());
+
+        // close to the line of the segment but past its end and moving away
+        let hit = swept_circle_segment(Vect::new(25f32, 5f32), 10f32, Vect::new(4f32, -1f32), Vect::ZERO, Vect::new(10f32, 0f32));
+        assert_eq!(None, hit);
+    }
+}
@@ -4,6 +4,7 @@ use crate::math::clamp_f;
 /// Vect is 2D vector and is used all over the place. I choose to use f32 because
 /// opengl also accepts only f32
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vect{
     pub x: f32,
     pub y: f32,
@@ -120,6 +121,12 @@ impl Vect{
         self.x * b.x + self.y * b.y
     }
 
+    /// cross returns z component of cross product of vectors
+    #[inline]
+    pub fn cross(self, b: Self) -> f32 {
+        self.x * b.y - self.y * b.x
+    }
+
     /// ang_to returns smallest angle between two vectors
     #[inline]
     pub fn ang_to(self, b: Self) -> f32 {